@@ -11,7 +11,7 @@ const ERROR_CODE_EXCEPTIONS: [u8; 10] = [0x8, 0xa, 0xb, 0xc, 0xd, 0xe, 0x11, 0x1
 /// and requires this to be defined:
 ///
 /// ```
-/// interrupt_handler(i: u16, frame: *const InterruptFrame)
+/// extern "C" fn interrupt_handler(i: u32, frame: *const InterruptFrame)
 /// ```
 #[proc_macro]
 pub fn interrupt_table(input: TokenStream) -> TokenStream {
@@ -48,8 +48,9 @@ pub fn interrupt_table(input: TokenStream) -> TokenStream {
                         "push esp",
                         concat!("push ", #i_str),
                         "call interrupt_handler",
-                        "add esp, 12", // Pop the stack, along with the error code
+                        "add esp, 8", // Pop the vector and the frame pointer
                         "popad", // Restore general purpose registers
+                        "add esp, 4", // Pop the error code
                         "iretd", // Return from interrupt
                     );
                 }
@@ -89,6 +90,8 @@ pub fn interrupt_table(input: TokenStream) -> TokenStream {
 /// ```
 /// The frame passed to the isr is contains the latest and greatest information
 /// hot and fresh from the CPU. Please don't sleep here.
+///
+/// Hardware IRQs are acknowledged before the body runs, spurious ones never reach it.
 pub fn isr(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input_fn = parse_macro_input!(item as ItemFn);
     let number = parse_macro_input!(attr as LitInt)
@@ -102,6 +105,7 @@ pub fn isr(attr: TokenStream, item: TokenStream) -> TokenStream {
         proc_macro2::Span::call_site(),
     );
     let body = input_fn.block;
+    let inputs = input_fn.sig.inputs;
 
     let link_section = format!(".isr.{}", number);
 
@@ -155,8 +159,9 @@ pub fn isr(attr: TokenStream, item: TokenStream) -> TokenStream {
                     "pushad", // Push general purpose registers
                     "push esp",
                     "call {}",
-                    "add esp, 12", // Pop the stack, along with the error code
+                    "add esp, 4", // Pop the frame pointer
                     "popad", // Restore general purpose registers
+                    "add esp, 4", // Pop the error code
                     "iretd", // Return from interrupt
                     sym #ident,
                 );
@@ -170,8 +175,10 @@ pub fn isr(attr: TokenStream, item: TokenStream) -> TokenStream {
         };
 
         #[no_mangle]
-        unsafe extern "C" fn #ident() {
-            crate::io::outb(0x20, 0x20);
+        unsafe extern "C" fn #ident(#inputs) {
+            if !crate::pic::PIC::acknowledge(#number) {
+                return;
+            }
 
             #body
 
@@ -196,7 +203,7 @@ pub fn isr(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// way to do that is to use:
 ///
 /// ```
-/// outb(0x20, 0x20);
+/// PIC::acknowledge(<num>);
 /// ```
 pub fn interrupt_handler(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input_fn = parse_macro_input!(item as ItemFn);
@@ -1,4 +1,88 @@
+use global::global;
+
+use crate::memory::{
+    phys_to_virt, MemoryMap, KERNEL_BASE, KERNEL_SPACE_SIZE, KERNEL_STACK, MEMORY_MAP,
+};
+use crate::paging::{
+    pagetable::PageTableEntry, Flags, ENTRIES_PER_TABLE, PAGE_IS_LARGE, PAGE_IS_PRESENT,
+    PAGE_IS_WRITABLE, PAGE_SIZE,
+};
+
+mod multiboot;
+pub use multiboot::Module;
+
 core::arch::global_asm!(include_str!("x86.S"), options(att_syntax));
+core::arch::global_asm!(include_str!("multiboot.S"), options(att_syntax));
+
+/// What the boot sector passes kmain in place of the multiboot magic
+pub const BOOT_SECTOR_MAGIC: u32 = 0;
+
+const MAX_CMDLINE: usize = 256;
+const MAX_MODULES: usize = 8;
+
+const LARGE_PAGE_SIZE: usize = ENTRIES_PER_TABLE * PAGE_SIZE;
+const KERNEL_PAGE_FLAGS: Flags = PAGE_IS_PRESENT | PAGE_IS_WRITABLE | PAGE_IS_LARGE;
+const CR4_PSE: usize = 1 << 4;
+const CR0_PG: usize = 1 << 31;
+
+/// Maps all of KERNEL_SPACE_SIZE at KERNEL_BASE with 4 MiB pages, supervisor only.
+/// It becomes the kernel's page directory once kmain runs.
+#[repr(C, align(4096))]
+struct BootPageDirectory([PageTableEntry; ENTRIES_PER_TABLE]);
+
+#[no_mangle]
+static mut BOOT_PAGE_DIRECTORY: BootPageDirectory =
+    BootPageDirectory(unsafe { core::mem::zeroed() });
+
+/// Set up the boot page directory and turn on paging.
+/// The first 4 MiB are also mapped where they are, so the boot code can go on until it jumps.
+/// This runs before paging, so everything is addressed physically.
+#[no_mangle]
+#[naked]
+#[link_section = ".start.paging"] // _start must come first
+extern "C" fn _boot_paging() {
+    unsafe {
+        core::arch::naked_asm!(
+            ".code32",
+            "lea edi, [{directory} - {base}]",
+            "xor eax, eax",
+            "mov ecx, {entries}",
+            "rep stosd",
+            "lea edi, [{directory} - {base}]",
+            "mov dword ptr [edi], {flags}",
+            "lea edi, [edi + {first} * 4]",
+            "mov eax, {flags}",
+            "mov ecx, {count}",
+            "2:",
+            "stosd",
+            "add eax, {large}",
+            "loop 2b",
+            "mov eax, cr4",
+            "or eax, {pse}",
+            "mov cr4, eax",
+            "lea eax, [{directory} - {base}]",
+            "mov cr3, eax",
+            "mov eax, cr0",
+            "or eax, {pg}",
+            "mov cr0, eax",
+            "ret",
+            directory = sym BOOT_PAGE_DIRECTORY,
+            base = const KERNEL_BASE,
+            entries = const ENTRIES_PER_TABLE,
+            flags = const KERNEL_PAGE_FLAGS,
+            first = const KERNEL_BASE / LARGE_PAGE_SIZE,
+            count = const KERNEL_SPACE_SIZE / LARGE_PAGE_SIZE,
+            large = const LARGE_PAGE_SIZE,
+            pse = const CR4_PSE,
+            pg = const CR0_PG,
+        );
+    }
+}
+
+/// Where the boot code left the directory the kernel is mapped with
+pub(crate) fn page_directory() -> *mut PageTableEntry {
+    (&raw mut BOOT_PAGE_DIRECTORY).cast()
+}
 
 #[no_mangle]
 #[naked]
@@ -13,21 +97,90 @@ extern "C" fn _start() -> ! {
             "mov fs, ax",
             "mov gs, ax",
             "mov ss, ax",
-            "mov ebp, 0x00200000",
-            "mov esp, ebp",
+            "mov esp, 0x00200000",
             "in al, 0x92",
             "or al, 2",
             "out 0x92, al",
-            "mov al, 0b00010001",
-            "out 0x20, al",
-            "mov al, 0x20",
-            "out 0x21, al",
-            "mov al, 0b00000001",
-            "out 0x21, al",
+            "call _boot_paging",
+            "mov ebp, {stack}", // From here on the kernel runs where it is linked
+            "mov esp, ebp",
+            "push {map}", // kmain gets the memory map the boot sector left
+            "push {magic}",
             "call kmain",
             "42:",
             "hlt",
             "jmp 42b",
+            stack = const KERNEL_STACK,
+            map = const MEMORY_MAP,
+            magic = const BOOT_SECTOR_MAGIC,
         );
     }
 }
+
+/// What the kernel was booted with, copied out of the loader's memory
+#[derive(Clone, Copy)]
+pub struct BootInfo {
+    memory_map: Option<&'static MemoryMap>,
+    cmdline: [u8; MAX_CMDLINE],
+    cmdline_len: usize,
+    modules: [Module; MAX_MODULES],
+    module_count: usize,
+}
+
+global!(Boot, BootInfo, BootInfo::new(), "BOOT_INFO");
+
+impl Boot {
+    /// Collect what @magic says was left at physical address @info,
+    /// kmain calls this once before anything else. A copy of what was set is returned.
+    pub fn init(magic: u32, info: usize) -> BootInfo {
+        let mut boot = BootInfo::new();
+
+        match magic {
+            multiboot::BOOTLOADER_MAGIC => multiboot::parse(&mut boot, info),
+            BOOT_SECTOR_MAGIC => {
+                boot.memory_map = Some(unsafe { &*(phys_to_virt(info) as *const MemoryMap) })
+            }
+            _ => panic!("Booted by something unknown: 0x{magic:x}"),
+        }
+
+        Self::set(|this| *this = boot);
+        boot
+    }
+}
+
+impl BootInfo {
+    const fn new() -> Self {
+        Self {
+            memory_map: None,
+            cmdline: [0; MAX_CMDLINE],
+            cmdline_len: 0,
+            modules: [Module::empty(); MAX_MODULES],
+            module_count: 0,
+        }
+    }
+
+    pub fn memory_map(&self) -> &'static MemoryMap {
+        self.memory_map.expect("No memory map")
+    }
+
+    /// The boot options, empty when booted from the boot sector
+    pub fn cmdline(&self) -> &str {
+        core::str::from_utf8(&self.cmdline[..self.cmdline_len]).unwrap_or("")
+    }
+
+    /// What the loader put in memory next to the kernel
+    pub fn modules(&self) -> &[Module] {
+        &self.modules[..self.module_count]
+    }
+
+    fn set_cmdline(&mut self, cmdline: &[u8]) {
+        self.cmdline_len = copy_str(&mut self.cmdline, cmdline);
+    }
+}
+
+/// Copy as much of @src as fits into @dst, returns how much that was
+fn copy_str(dst: &mut [u8], src: &[u8]) -> usize {
+    let len = src.len().min(dst.len());
+    dst[..len].copy_from_slice(&src[..len]);
+    len
+}
@@ -0,0 +1,148 @@
+use core::ffi::{c_char, CStr};
+
+use global::global;
+
+use super::{copy_str, BootInfo, MAX_MODULES};
+use crate::memory::{self, phys_to_virt, MemoryMap};
+use crate::println;
+
+/// What a multiboot loader leaves in EAX
+pub const BOOTLOADER_MAGIC: u32 = 0x2BADB002;
+
+// Which parts of the info are there
+const INFO_MEMORY: u32 = 1 << 0;
+const INFO_CMDLINE: u32 = 1 << 2;
+const INFO_MODULES: u32 = 1 << 3;
+const INFO_MEMORY_MAP: u32 = 1 << 6;
+
+const USABLE: u32 = 1;
+const LOWER_MEMORY_START: u64 = 0;
+const UPPER_MEMORY_START: u64 = 0x100000;
+
+#[repr(C)]
+struct Info {
+    flags: u32,
+    mem_lower: u32, // KiB from 0
+    mem_upper: u32, // KiB from 1MiB
+    boot_device: u32,
+    cmdline: u32,
+    mods_count: u32,
+    mods_addr: u32,
+    syms: [u32; 4],
+    mmap_length: u32,
+    mmap_addr: u32,
+}
+
+#[repr(C, packed)]
+struct MemoryMapEntry {
+    size: u32, // Of the rest of the entry
+    base: u64,
+    length: u64,
+    kind: u32,
+}
+
+#[repr(C)]
+struct ModuleEntry {
+    start: u32,
+    end: u32,
+    cmdline: u32,
+    reserved: u32,
+}
+
+// Longer module command lines are cut short
+const MAX_MODULE_CMDLINE: usize = 64;
+
+/// A module the loader put in memory, clear of everything the kernel has at fixed places.
+/// @start and @end are physical.
+#[derive(Clone, Copy)]
+pub struct Module {
+    pub start: usize,
+    pub end: usize,
+    cmdline: [u8; MAX_MODULE_CMDLINE],
+    cmdline_len: usize,
+}
+
+impl Module {
+    pub(super) const fn empty() -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            cmdline: [0; MAX_MODULE_CMDLINE],
+            cmdline_len: 0,
+        }
+    }
+
+    /// What the loader was told to load it with, copied out of the loader's memory
+    pub fn cmdline(&self) -> &str {
+        core::str::from_utf8(&self.cmdline[..self.cmdline_len]).unwrap_or("")
+    }
+}
+
+// The loader's map in the format the boot sector uses
+global!(MultibootMap, MemoryMap, MemoryMap::empty(), "MULTIBOOT_MAP");
+
+/// Everything the loader hands us is addressed physically
+pub(super) fn parse(boot: &mut BootInfo, info: usize) {
+    let info = unsafe { &*(phys_to_virt(info) as *const Info) };
+
+    MultibootMap::set(|map| parse_memory(map, info));
+    // Nothing writes it after this, so it can be handed out for good
+    boot.memory_map =
+        Some(MultibootMap::get().with_rlock(|map| unsafe { &*(map as *const MemoryMap) }));
+
+    if info.flags & INFO_CMDLINE != 0 {
+        boot.set_cmdline(unsafe { c_str(info.cmdline) }.to_bytes());
+    }
+
+    if info.flags & INFO_MODULES != 0 {
+        for i in 0..info.mods_count as usize {
+            let modules = phys_to_virt(info.mods_addr as usize) as *const ModuleEntry;
+            let entry = unsafe { &*modules.add(i) };
+            let mut module = Module {
+                start: entry.start as usize,
+                end: entry.end as usize,
+                ..Module::empty()
+            };
+            module.cmdline_len = copy_str(
+                &mut module.cmdline,
+                unsafe { c_str(entry.cmdline) }.to_bytes(),
+            );
+
+            // The kernel would overwrite it, or hand it out
+            if memory::is_reserved(module.start..module.end) || boot.module_count == MAX_MODULES {
+                println!("Ignoring module {}", module.cmdline());
+                continue;
+            }
+
+            boot.modules[boot.module_count] = module;
+            boot.module_count += 1;
+        }
+    }
+}
+
+/// Fill @map from the loader's map, or from the memory sizes if there is none
+fn parse_memory(map: &mut MemoryMap, info: &Info) {
+    if info.flags & INFO_MEMORY_MAP != 0 {
+        let mut entry = phys_to_virt(info.mmap_addr as usize);
+        let end = entry + info.mmap_length as usize;
+        while entry < end {
+            let region = unsafe { &*(entry as *const MemoryMapEntry) };
+            if !map.push(region.base, region.length, region.kind) {
+                break;
+            }
+            entry += region.size as usize + core::mem::size_of::<u32>();
+        }
+    } else if info.flags & INFO_MEMORY != 0 {
+        let lower = info.mem_lower as u64 * 1024;
+        let upper = info.mem_upper as u64 * 1024;
+        map.push(LOWER_MEMORY_START, lower, USABLE);
+        map.push(UPPER_MEMORY_START, upper, USABLE);
+    }
+}
+
+/// # Safety
+/// @addr must point at a NUL terminated string, it has to be copied before the loader's
+/// memory is reused
+unsafe fn c_str(addr: u32) -> &'static CStr {
+    unsafe { CStr::from_ptr(phys_to_virt(addr as usize) as *const c_char) }
+}
@@ -17,6 +17,7 @@ impl<T> Box<T> {
 
 impl<T: ?Sized> Drop for Box<T> {
     fn drop(&mut self) {
+        unsafe { core::ptr::drop_in_place(self.0.as_ptr()) };
         free!(self.0.as_ptr())
     }
 }
@@ -1,9 +1,11 @@
 pub mod arr;
 pub mod r#box;
 pub mod r#dyn;
+pub mod ring;
 pub mod vec;
 
 pub use arr::Array;
 pub use r#box::Box;
 pub use r#dyn::Dyn;
+pub use ring::RingBuffer;
 pub use vec::Vec;
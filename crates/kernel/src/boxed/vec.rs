@@ -66,7 +66,7 @@ impl<T: Copy> Vec<T> {
         unsafe {
             self.data = Unique::new_unchecked(core::mem::transmute::<*mut u8, *mut T>(realloc!(
                 self.data.as_ptr() as *mut u8,
-                2 * self.cap
+                2 * self.cap * core::mem::size_of::<T>()
             )));
         }
         self.cap *= 2;
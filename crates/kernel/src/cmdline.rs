@@ -0,0 +1,189 @@
+use global::global;
+
+use crate::{
+    fs::{FileMode, VFS},
+    path::{Path, MAX_PATH},
+    println, traceln,
+};
+
+/// Read when the boot loader didn't give us a command line
+pub const BOOT_CONFIG: &str = "0:/BOOT.CFG";
+
+/// Traces are dropped below this
+pub const LOGLEVEL_QUIET: u8 = 0;
+pub const LOGLEVEL_TRACE: u8 = 1;
+
+const DEFAULT_INIT: &str = "SHELL";
+const DEFAULT_ROOT: &str = "0:";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleDevice {
+    Vga,
+    Serial, // The screen is mirrored to COM1
+}
+
+/// What the kernel was told to do at boot, set once before the first process
+#[derive(Clone, Copy)]
+pub struct BootOptions {
+    init: [u8; MAX_PATH],
+    init_len: usize,
+    root: [u8; MAX_PATH],
+    root_len: usize,
+    loglevel: u8,
+    console: ConsoleDevice,
+}
+
+global!(Options, BootOptions, BootOptions::new(), "BOOT_OPTIONS");
+
+impl Options {
+    /// Parse @cmdline, or BOOT_CONFIG if it is empty. The filesystem must be resolved.
+    /// A copy of what was set is returned.
+    pub fn init(cmdline: &str) -> BootOptions {
+        let mut config = None;
+        if cmdline.trim().is_empty() {
+            config = VFS::open(Path::new(BOOT_CONFIG), FileMode::READ_ONLY)
+                .and_then(|file| file.read_all())
+                .ok();
+        }
+
+        let options = match config {
+            Some(ref config) => core::str::from_utf8(config).unwrap_or(""),
+            None => cmdline,
+        };
+        // Parsed outside the lock, bad options are printed and printing reads the loglevel
+        let mut parsed = BootOptions::new();
+        parsed.parse(options);
+
+        if let Some(mut config) = config {
+            config.free();
+        }
+
+        Self::set(|this| *this = parsed);
+        traceln!(
+            "Boot options: init={} root={} loglevel={}",
+            parsed.init_program(),
+            parsed.root(),
+            parsed.loglevel
+        );
+
+        parsed
+    }
+
+    pub fn loglevel() -> u8 {
+        Self::get().with_rlock(BootOptions::loglevel)
+    }
+
+    /// See BootOptions::resolve
+    pub fn resolve<'a>(path: &str, buf: &'a mut [u8; MAX_PATH]) -> Option<&'a str> {
+        Self::get().with_rlock(|options| options.resolve(path, buf))
+    }
+}
+
+impl BootOptions {
+    fn new() -> Self {
+        let mut this = Self {
+            init: [0; MAX_PATH],
+            init_len: 0,
+            root: [0; MAX_PATH],
+            root_len: 0,
+            loglevel: LOGLEVEL_TRACE,
+            console: ConsoleDevice::Vga,
+        };
+
+        Self::store(&mut this.init, &mut this.init_len, DEFAULT_INIT);
+        Self::store(&mut this.root, &mut this.root_len, DEFAULT_ROOT);
+
+        this
+    }
+
+    /// The first program to run, relative to the root unless it names a disk
+    pub fn init_program(&self) -> &str {
+        core::str::from_utf8(&self.init[..self.init_len]).unwrap_or(DEFAULT_INIT)
+    }
+
+    /// The disk programs are looked up on, like `0:`
+    pub fn root(&self) -> &str {
+        core::str::from_utf8(&self.root[..self.root_len]).unwrap_or(DEFAULT_ROOT)
+    }
+
+    pub fn loglevel(&self) -> u8 {
+        self.loglevel
+    }
+
+    pub fn console(&self) -> ConsoleDevice {
+        self.console
+    }
+
+    /// Put @path in @buf, prefixed with the root if it doesn't name a disk.
+    /// None if it doesn't fit.
+    pub fn resolve<'a>(&self, path: &str, buf: &'a mut [u8; MAX_PATH]) -> Option<&'a str> {
+        if Self::disk_prefix(path).is_some() {
+            buf.get_mut(..path.len())?.copy_from_slice(path.as_bytes());
+            return core::str::from_utf8(&buf[..path.len()]).ok();
+        }
+
+        let root = self.root();
+        let path = path.trim_start_matches('/');
+        let len = root.len() + 1 + path.len();
+
+        buf.get_mut(..root.len())?.copy_from_slice(root.as_bytes());
+        buf[root.len()] = b'/';
+        buf.get_mut(root.len() + 1..len)?
+            .copy_from_slice(path.as_bytes());
+
+        core::str::from_utf8(&buf[..len]).ok()
+    }
+
+    /// `key=value` options separated by whitespace, `#` comments out the rest of a line
+    fn parse(&mut self, cmdline: &str) {
+        let options = cmdline
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(str::split_ascii_whitespace);
+
+        for option in options {
+            let Some((key, value)) = option.split_once('=') else {
+                println!("Ignoring boot option {}", option);
+                continue;
+            };
+
+            let ok = match key {
+                "init" => Self::store(&mut self.init, &mut self.init_len, value),
+                // Only a disk, `0:/BIN` would otherwise be taken as `0:`
+                "root" => Self::disk_prefix(value)
+                    .filter(|&disk| disk == value)
+                    .is_some_and(|disk| Self::store(&mut self.root, &mut self.root_len, disk)),
+                "loglevel" => value.parse().map(|level| self.loglevel = level).is_ok(),
+                "console" => match value {
+                    "vga" => Some(ConsoleDevice::Vga),
+                    "serial" => Some(ConsoleDevice::Serial),
+                    _ => None,
+                }
+                .map(|console| self.console = console)
+                .is_some(),
+                _ => false,
+            };
+
+            if !ok {
+                println!("Ignoring boot option {}", option);
+            }
+        }
+    }
+
+    fn store(buf: &mut [u8; MAX_PATH], len: &mut usize, value: &str) -> bool {
+        if value.is_empty() || value.len() > MAX_PATH {
+            return false;
+        }
+
+        buf[..value.len()].copy_from_slice(value.as_bytes());
+        *len = value.len();
+        true
+    }
+
+    /// The `0:` in `0:/SHELL`
+    fn disk_prefix(path: &str) -> Option<&str> {
+        let prefix = path.get(..2)?;
+        let bytes = prefix.as_bytes();
+        (bytes[0].is_ascii_digit() && bytes[1] == b':').then_some(prefix)
+    }
+}
@@ -57,17 +57,14 @@ impl Registers {
     pub unsafe extern "C" fn restore(regs: *const Registers) {
         naked_asm!(
             r#"
-            push ebp
-            mov ebp, esp
-            mov ebx, [ebp+8] // ebx = regs;
-            mov edi, [ebx]   // edi = *regs;
-            mov esi, [ebx+4] // esi = *(regs + 4);
-            mov ebp, [ebx+8] // ebp = *(regs + 8);
-            mov edx, [ebx+16]
-            mov ecx, [ebx+20]
-            mov eax, [ebx+24]
-            mov ebx, [ebx+12]
-            pop ebp
+            mov ebx, [esp+4]  // ebx = regs;
+            mov edi, [ebx]    // edi = regs->edi;
+            mov esi, [ebx+4]  // esi = regs->esi;
+            mov ebp, [ebx+8]  // ebp = regs->ebp;
+            mov edx, [ebx+20] // edx = regs->edx;
+            mov ecx, [ebx+24] // ecx = regs->ecx;
+            mov eax, [ebx+28] // eax = regs->eax;
+            mov ebx, [ebx+16] // ebx = regs->ebx;
             ret
         "#,
         )
@@ -87,6 +84,9 @@ impl CPU {
             task.registers
         });
 
+        // We never come back here, so don't hold on to the task
+        drop(task);
+
         unsafe { Self::_user_return(&registers) };
     }
 
@@ -111,12 +111,10 @@ impl CPU {
             // Push the stack pointer
             push ecx
 
-            // Push the flags
+            // Push the flags the task was interrupted with (regs->flags)
             // We need to set the IF (Interrupt Enable flag) otherwise the user process might never yield
-            pushfd
-            pop ecx
-            or ecx, 0x200
-            push ecx
+            push dword ptr [eax+44]
+            or dword ptr [esp], 0x200
 
             // Push the code segment
             push edx
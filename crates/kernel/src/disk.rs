@@ -4,7 +4,7 @@ use crate::{
     boxed::{Dyn, Vec},
     fs::FileSystem,
     global::global,
-    io::{insb, insw, outb},
+    io::{insb, insw, outb, outw},
     spinwhile,
     sync::Global,
 };
@@ -16,6 +16,12 @@ pub struct Offset(pub usize);
 
 const SECTOR_SIZE: usize = 512;
 
+const ATA_STATUS: u16 = 0x1F7;
+const ATA_STATUS_ERROR: u8 = 1 << 0;
+const ATA_STATUS_DATA_REQUEST: u8 = 1 << 3;
+const ATA_STATUS_FAULT: u8 = 1 << 5;
+const ATA_STATUS_BUSY: u8 = 1 << 7;
+
 #[derive(Debug)]
 pub enum IOError {
     Other,
@@ -78,9 +84,7 @@ pub trait Stream {
     fn seek_sector(&mut self, pos: Sector);
     fn pos(&self) -> Offset;
     fn read(&mut self, buf: &mut [u8], total: usize);
-    fn write(&mut self) {
-        todo!();
-    }
+    fn write(&mut self, buf: &[u8], total: usize) -> Result<(), IOError>;
     fn sector_size(&self) -> usize;
 }
 
@@ -126,6 +130,32 @@ impl Stream for Streamer<'_> {
         }
     }
 
+    /// Sectors that are only partly written are read in first.
+    /// The drive's cache is flushed once all of them are written.
+    fn write(&mut self, buf: &[u8], total: usize) -> Result<(), IOError> {
+        let mut bytes_written = 0;
+
+        while bytes_written != total {
+            let sector = self.pos / SECTOR_SIZE;
+            let offset = self.pos % SECTOR_SIZE;
+            let bytes_to_write = (total - bytes_written).min(SECTOR_SIZE - offset);
+            let mut local: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
+
+            if bytes_to_write != SECTOR_SIZE {
+                self.read_sector(sector as u32, &mut local);
+            }
+            local[offset..(offset + bytes_to_write)]
+                .copy_from_slice(&buf[bytes_written..(bytes_written + bytes_to_write)]);
+
+            self.write_sector(sector as u32, &local)?;
+
+            self.pos += bytes_to_write;
+            bytes_written += bytes_to_write;
+        }
+
+        self.flush()
+    }
+
     fn sector_size(&self) -> usize {
         self.disk.sector_size
     }
@@ -154,6 +184,57 @@ impl<'a> Streamer<'a> {
         }
     }
 
+    fn write_sector(&self, lba: u32, buf: &[u8; SECTOR_SIZE]) -> Result<(), IOError> {
+        spinwhile!(insb(ATA_STATUS) & ATA_STATUS_BUSY != 0);
+
+        outb(0x1F6, ((lba >> 24) | 0xE0) as u8);
+        outb(0x1F2, 1);
+        outb(0x1F3, (lba & 0xff) as u8);
+        outb(0x1F4, (lba >> 8) as u8);
+        outb(0x1F5, (lba >> 16) as u8);
+        outb(0x1F7, 0x30);
+
+        self.wait_for_data()?;
+        for i in 0..(SECTOR_SIZE / 2) {
+            outw(0x1F0, buf[2 * i] as u16 | (buf[2 * i + 1] as u16) << 8);
+        }
+
+        // It is still busy with the data for a while
+        self.wait_until_done()
+    }
+
+    /// Flush the drive's cache, so what was written is on disk when we return
+    fn flush(&self) -> Result<(), IOError> {
+        outb(0x1F7, 0xE7);
+        self.wait_until_done()
+    }
+
+    /// Wait until the drive wants the data of a sector, Err if the command failed instead
+    fn wait_for_data(&self) -> Result<(), IOError> {
+        loop {
+            let status = insb(ATA_STATUS);
+            if status & ATA_STATUS_BUSY != 0 {
+                continue;
+            }
+            if status & (ATA_STATUS_ERROR | ATA_STATUS_FAULT) != 0 {
+                return Err(IOError::Other);
+            }
+            if status & ATA_STATUS_DATA_REQUEST != 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Wait until the drive is no longer busy, Err if the command failed
+    fn wait_until_done(&self) -> Result<(), IOError> {
+        spinwhile!(insb(ATA_STATUS) & ATA_STATUS_BUSY != 0);
+
+        match insb(ATA_STATUS) & (ATA_STATUS_ERROR | ATA_STATUS_FAULT) {
+            0 => Ok(()),
+            _ => Err(IOError::Other),
+        }
+    }
+
     pub fn read_new<T: FromBytes<Output = T> + Sized>(&mut self) -> T {
         let size = core::mem::size_of::<T>();
 
@@ -0,0 +1,254 @@
+use core::arch::asm;
+use core::fmt::{self, Display};
+
+use interrupts::isr;
+
+use crate::{
+    cpu::InterruptFrame,
+    paging::Addr,
+    println,
+    process::{CurrentProcess, Process, Scheduler},
+    traceln,
+};
+
+/// The first 32 vectors belong to the CPU
+pub const NUM_EXCEPTIONS: u32 = 32;
+
+/// A process killed by an exception exits with this plus the vector
+pub const EXCEPTION_EXIT_CODE: usize = 128;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    DivideError,
+    Debug,
+    NonMaskableInterrupt,
+    Breakpoint,
+    Overflow,
+    BoundRangeExceeded,
+    InvalidOpcode,
+    DeviceNotAvailable,
+    DoubleFault,
+    CoprocessorSegmentOverrun,
+    InvalidTss,
+    SegmentNotPresent,
+    StackSegmentFault,
+    GeneralProtection,
+    PageFault,
+    X87FloatingPoint,
+    AlignmentCheck,
+    MachineCheck,
+    SimdFloatingPoint,
+    Virtualization,
+    ControlProtection,
+    HypervisorInjection,
+    VmmCommunication,
+    Security,
+    Reserved(u8),
+}
+
+impl Exception {
+    pub fn from_vector(vector: u8) -> Self {
+        match vector {
+            0 => Self::DivideError,
+            1 => Self::Debug,
+            2 => Self::NonMaskableInterrupt,
+            3 => Self::Breakpoint,
+            4 => Self::Overflow,
+            5 => Self::BoundRangeExceeded,
+            6 => Self::InvalidOpcode,
+            7 => Self::DeviceNotAvailable,
+            8 => Self::DoubleFault,
+            9 => Self::CoprocessorSegmentOverrun,
+            10 => Self::InvalidTss,
+            11 => Self::SegmentNotPresent,
+            12 => Self::StackSegmentFault,
+            13 => Self::GeneralProtection,
+            14 => Self::PageFault,
+            16 => Self::X87FloatingPoint,
+            17 => Self::AlignmentCheck,
+            18 => Self::MachineCheck,
+            19 => Self::SimdFloatingPoint,
+            20 => Self::Virtualization,
+            21 => Self::ControlProtection,
+            28 => Self::HypervisorInjection,
+            29 => Self::VmmCommunication,
+            30 => Self::Security,
+            vector => Self::Reserved(vector),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::DivideError => "Divide Error",
+            Self::Debug => "Debug",
+            Self::NonMaskableInterrupt => "Non-Maskable Interrupt",
+            Self::Breakpoint => "Breakpoint",
+            Self::Overflow => "Overflow",
+            Self::BoundRangeExceeded => "Bound Range Exceeded",
+            Self::InvalidOpcode => "Invalid Opcode",
+            Self::DeviceNotAvailable => "Device Not Available",
+            Self::DoubleFault => "Double Fault",
+            Self::CoprocessorSegmentOverrun => "Coprocessor Segment Overrun",
+            Self::InvalidTss => "Invalid TSS",
+            Self::SegmentNotPresent => "Segment Not Present",
+            Self::StackSegmentFault => "Stack-Segment Fault",
+            Self::GeneralProtection => "General Protection Fault",
+            Self::PageFault => "Page Fault",
+            Self::X87FloatingPoint => "x87 Floating-Point Exception",
+            Self::AlignmentCheck => "Alignment Check",
+            Self::MachineCheck => "Machine Check",
+            Self::SimdFloatingPoint => "SIMD Floating-Point Exception",
+            Self::Virtualization => "Virtualization Exception",
+            Self::ControlProtection => "Control Protection Exception",
+            Self::HypervisorInjection => "Hypervisor Injection Exception",
+            Self::VmmCommunication => "VMM Communication Exception",
+            Self::Security => "Security Exception",
+            Self::Reserved(_) => "Reserved Exception",
+        }
+    }
+
+    /// Decode what the CPU pushed as the error code of this exception
+    fn error_code(&self, code: usize) -> ErrorCode {
+        match self {
+            Self::InvalidTss
+            | Self::SegmentNotPresent
+            | Self::StackSegmentFault
+            | Self::GeneralProtection => ErrorCode::Selector(code),
+            Self::PageFault => ErrorCode::PageFault(code, faulting_address()),
+            Self::DoubleFault
+            | Self::AlignmentCheck
+            | Self::ControlProtection
+            | Self::VmmCommunication
+            | Self::Security => ErrorCode::Raw(code),
+            _ => ErrorCode::None,
+        }
+    }
+}
+
+impl Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reserved(vector) => write!(f, "{} ({vector})", self.name()),
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+enum ErrorCode {
+    None,
+    Raw(usize),
+    Selector(usize),
+    PageFault(usize, usize), // Error code and the address that faulted
+}
+
+// Selector error code
+const SELECTOR_EXTERNAL: usize = 1 << 0;
+const SELECTOR_TABLE_SHIFT: usize = 1;
+const SELECTOR_INDEX_SHIFT: usize = 3;
+
+// Page fault error code
+const PF_PRESENT: usize = 1 << 0;
+const PF_WRITE: usize = 1 << 1;
+const PF_USER: usize = 1 << 2;
+const PF_RESERVED: usize = 1 << 3;
+const PF_INSTRUCTION: usize = 1 << 4;
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ErrorCode::None => Ok(()),
+            ErrorCode::Raw(code) => write!(f, "error code 0x{code:x}"),
+            ErrorCode::Selector(0) => write!(f, "no selector"),
+            ErrorCode::Selector(code) => {
+                let table = match (code >> SELECTOR_TABLE_SHIFT) & 0b11 {
+                    0 => "GDT",
+                    2 => "LDT",
+                    _ => "IDT",
+                };
+                let origin = if code & SELECTOR_EXTERNAL != 0 {
+                    ", external"
+                } else {
+                    ""
+                };
+
+                write!(
+                    f,
+                    "selector {table}[{}]{origin}",
+                    (code & 0xFFFF) >> SELECTOR_INDEX_SHIFT
+                )
+            }
+            ErrorCode::PageFault(code, address) => {
+                let cause = if code & PF_PRESENT != 0 {
+                    "protection violation"
+                } else {
+                    "page not present"
+                };
+                let access = if code & PF_INSTRUCTION != 0 {
+                    "fetch"
+                } else if code & PF_WRITE != 0 {
+                    "write"
+                } else {
+                    "read"
+                };
+                let mode = if code & PF_USER != 0 {
+                    "user"
+                } else {
+                    "kernel"
+                };
+
+                write!(f, "{mode} {access} at 0x{address:08x}, {cause}")?;
+                if code & PF_RESERVED != 0 {
+                    write!(f, ", reserved bit set")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// CR2 holds the address of the last page fault
+fn faulting_address() -> usize {
+    let address: usize;
+    unsafe { asm!("mov {}, cr2", out(reg) address) };
+    address
+}
+
+/// Exceptions from user land take down the process, in the kernel they are fatal.
+pub fn handle(vector: u8, frame: &InterruptFrame) {
+    let exception = Exception::from_vector(vector);
+    let code = exception.error_code(frame.errno());
+
+    // Not caused by whatever was running, there is nothing to blame it on
+    if exception == Exception::NonMaskableInterrupt {
+        traceln!("{}{}", exception, frame);
+        return;
+    }
+
+    if frame.cs & 0x3 != 0x3 {
+        panic!("{exception} in the kernel: {code}{frame}");
+    }
+
+    let process = CurrentProcess::get();
+    let pid = process.with_rlock(|process| process.pid());
+    traceln!("{} in process {}: {}{}", exception, pid, code, frame);
+    println!("Process {} killed: {}", pid, exception);
+
+    Process::mark_dead(process, EXCEPTION_EXIT_CODE + vector as usize);
+    Scheduler::schedule()
+}
+
+/// User pages that aren't present yet may belong to a demand-paged region,
+/// anything else is handled like any other exception.
+#[isr(14)]
+fn page_fault(frame: *const InterruptFrame) {
+    let frame = unsafe { &*frame };
+
+    if frame.cs & 0x3 == 0x3
+        && frame.errno() & PF_PRESENT == 0
+        && Process::fault_in(CurrentProcess::get(), Addr(faulting_address()))
+    {
+        return;
+    }
+
+    handle(14, frame)
+}
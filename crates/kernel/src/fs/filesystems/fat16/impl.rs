@@ -1,94 +1,369 @@
-use crate::{boxed::Array, disk::Stream};
-use core::mem;
+use crate::disk::{Offset, Stream};
+use crate::fs::DirEntry;
+use crate::rtc::DateTime;
+use crate::syscalls::{
+    DIRENT_ARCHIVED, DIRENT_DIRECTORY, DIRENT_HIDDEN, DIRENT_READ_ONLY, DIRENT_SYSTEM,
+};
+use crate::FromBytes;
+use alloc::{format, string::String, vec::Vec};
 
-use super::private::{FatDirectoryItem, FAT_DIRECTORY_ITEM_SIZE};
-use crate::disk::Offset;
+use super::private::{FatDirectoryItem, FatLongNameItem};
+use super::Fat16;
 
 pub(super) const FAT16_SIGNATURE: u8 = 0x29;
-const _FAT16_ENTRY_SIZE: u16 = 0x02;
-const _FAT16_BAD_SECTOR: u16 = 0xFF7;
-const _FAT16_UNUSED: u8 = 0xE0;
-
-const _FAT_FILE_READ_ONLY: u8 = 1 << 0;
-const _FAT_FILE_HIDDEN: u8 = 1 << 1;
-const _FAT_FILE_SYSTEM: u8 = 1 << 2;
-const _FAT_FILE_VOLUME_LABEL: u8 = 1 << 3;
+pub(super) const FAT16_ENTRY_SIZE: usize = 0x02;
+pub(super) const FAT16_FREE_CLUSTER: u16 = 0x0000;
+pub(super) const FAT16_BAD_CLUSTER: u16 = 0xFFF7;
+pub(super) const FAT16_END_OF_CHAIN: u16 = 0xFFF8; // And anything above
+pub(super) const FAT16_LAST_CLUSTER: u16 = 0xFFFF; // What we end chains with
+
+// The first byte of a name says whether the entry is in use
+pub(super) const FAT_ENTRY_END: u8 = 0x00; // Nothing after this one either
+pub(super) const FAT_ENTRY_DELETED: u8 = 0xE5;
+
+const FAT_FILE_READ_ONLY: u8 = 1 << 0;
+const FAT_FILE_HIDDEN: u8 = 1 << 1;
+const FAT_FILE_SYSTEM: u8 = 1 << 2;
+const FAT_FILE_VOLUME_LABEL: u8 = 1 << 3;
 const FAT_FILE_SUBDIRECTORY: u8 = 1 << 4;
-const _FAT_FILE_ARCHIVED: u8 = 1 << 5;
-const _FAT_FILE_DEVICE: u8 = 1 << 6;
-const _FAT_FILE_RESERVERED: u8 = 1 << 7;
+const FAT_FILE_ARCHIVED: u8 = 1 << 5;
+const FAT_FILE_DEVICE: u8 = 1 << 6;
+const FAT_FILE_RESERVERED: u8 = 1 << 7;
+// What the attributes of a long name piece are, no real item has all of them
+const FAT_FILE_LONG_NAME: u8 =
+    FAT_FILE_READ_ONLY | FAT_FILE_HIDDEN | FAT_FILE_SYSTEM | FAT_FILE_VOLUME_LABEL;
 
-pub(super) struct FatDirectory {
-    items: Array<FatDirectoryItem>,
-    pub total: u32,
-    pub start: usize,
-    pub end: usize,
-}
+// Set in the reserved byte when the short name is shown in lowercase
+const FAT_LOWERCASE_NAME: u8 = 1 << 3;
+const FAT_LOWERCASE_EXTENSION: u8 = 1 << 4;
 
-impl FatDirectory {
-    pub fn new(stream: &mut dyn Stream, start: Offset, entries: usize) -> Self {
-        stream.seek(start);
-        let total = Self::get_total_items(stream);
+const FAT_LONG_NAME_LAST: u8 = 0x40; // In the order of the first piece on disk
+const FAT_LONG_NAME_ORDER: u8 = 0x1f;
+const FAT_LONG_NAME_CHARS: usize = 13; // UCS-2 characters in each piece
+const FAT_LONG_NAME_MAX: usize = 255;
 
-        let mut items = Array::new(entries);
-        for i in 0..total as usize {
-            let item = FatDirectoryItem::new(stream);
-            items[i] = item;
-        }
+// What can't be in a short name, besides lowercase letters and what isn't printable ASCII
+const FAT_NAME_INVALID: &[u8] = b" \"*+,./:;<=>?[\\]|";
+// What can't be in a long name, besides control characters
+const FAT_LONG_NAME_INVALID: &str = "\"*/:<>?\\|";
+
+impl FatDirectoryItem {
+    pub fn is_directory(&self) -> bool {
+        self.attributes & FAT_FILE_SUBDIRECTORY != 0
+    }
 
-        Self {
-            items,
-            total,
-            start: start.0 / stream.sector_size(),
-            end: (start.0 + entries * FAT_DIRECTORY_ITEM_SIZE) / stream.sector_size(),
+    /// Long name pieces have this bit set as well
+    pub fn is_volume_label(&self) -> bool {
+        self.attributes & FAT_FILE_VOLUME_LABEL != 0
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.attributes & FAT_FILE_READ_ONLY != 0
+    }
+
+    pub fn is_long_name(&self) -> bool {
+        self.attributes & !(FAT_FILE_DEVICE | FAT_FILE_RESERVERED) == FAT_FILE_LONG_NAME
+    }
+
+    /// Give the item the short name @name, None if it isn't an uppercase ASCII 8.3 name
+    pub fn set_name(&mut self, name: &str) -> Option<()> {
+        let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+        let valid = |part: &str, max| {
+            part.len() <= max
+                && part.bytes().all(|c| {
+                    c.is_ascii_graphic()
+                        && !c.is_ascii_lowercase()
+                        && !FAT_NAME_INVALID.contains(&c)
+                })
+        };
+        if base.is_empty() || !valid(base, 8) || !valid(ext, 3) {
+            return None;
         }
+
+        let mut filename = [b' '; 8];
+        let mut extension = [b' '; 3];
+        filename[..base.len()].copy_from_slice(base.as_bytes());
+        extension[..ext.len()].copy_from_slice(ext.as_bytes());
+
+        self.filename = filename;
+        self.extension = extension;
+        self.reserved &= !(FAT_LOWERCASE_NAME | FAT_LOWERCASE_EXTENSION);
+        Some(())
     }
 
-    /// This leaves @stream intact
-    fn get_total_items(stream: &mut dyn Stream) -> u32 {
-        let pos = stream.pos(); // We have to rewind when done
+    /// The short name with its extension, the way paths have it
+    pub fn short_name(&self) -> String {
+        let case = |part: &str, lowercase| match self.reserved & lowercase {
+            0 => String::from(part),
+            _ => part.to_ascii_lowercase(),
+        };
 
-        const SIZE: usize = mem::size_of::<FatDirectoryItem>();
-        let mut buf: [u8; SIZE] = [0; SIZE];
-        let mut count = 0;
-        loop {
-            stream.read(&mut buf, SIZE);
-            match buf[0] {
-                0 => break,
-                0xE5 => continue,
-                _ => count += 1,
-            }
+        let name = case(self.filename(), FAT_LOWERCASE_NAME);
+        match self.extension() {
+            "" => name,
+            ext => format!("{}.{}", name, case(ext, FAT_LOWERCASE_EXTENSION)),
         }
+    }
 
-        stream.seek(pos);
-        count
+    /// What the pieces of its long name have to say they belong to this item
+    pub fn checksum(&self) -> u8 {
+        self.filename
+            .iter()
+            .chain(self.extension.iter())
+            .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
     }
 
-    pub fn find(&self, stream: &mut dyn Stream, name: &str) -> Option<FatItem> {
-        for item in self.items.into_iter() {
-            if item.filename() == name {
-                return Some(FatItem::new(stream, item));
-            }
+    pub fn set_first_cluster(&mut self, cluster: usize) {
+        self.first_cluster = cluster as u16;
+    }
+
+    pub fn set_created(&mut self, now: DateTime) {
+        self.creation_time_ds = (now.second % 2) * 100;
+        self.creation_time = fat_time(now);
+        self.creation_dat = fat_date(now);
+        self.set_modified(now);
+    }
+
+    pub fn set_modified(&mut self, now: DateTime) {
+        self.last_mod_time = fat_time(now);
+        self.last_mod_data = fat_date(now);
+        self.last_access = fat_date(now);
+        self.attributes |= FAT_FILE_ARCHIVED;
+    }
+}
+
+/// Seconds are kept in steps of two
+fn fat_time(time: DateTime) -> u16 {
+    ((time.hour as u16) << 11) | ((time.minute as u16) << 5) | (time.second as u16 / 2)
+}
+
+/// Years are counted from 1980
+fn fat_date(date: DateTime) -> u16 {
+    (date.year.saturating_sub(1980) << 9) | ((date.month as u16) << 5) | date.day as u16
+}
+
+fn from_fat(date: u16, time: u16) -> DateTime {
+    DateTime {
+        year: 1980 + (date >> 9),
+        month: ((date >> 5) & 0x0f) as u8,
+        day: (date & 0x1f) as u8,
+        hour: (time >> 11) as u8,
+        minute: ((time >> 5) & 0x3f) as u8,
+        second: ((time & 0x1f) * 2) as u8,
+    }
+}
+
+impl FatLongNameItem {
+    fn chars(&self) -> impl Iterator<Item = u16> {
+        let (name1, name2, name3) = (self.name1, self.name2, self.name3);
+        name1.into_iter().chain(name2).chain(name3)
+    }
+}
+
+/// The pieces @name is kept in, in the order they go on disk, None if it can't be a long name
+pub(super) fn long_name_items(name: &str, checksum: u8) -> Option<Vec<FatLongNameItem>> {
+    let chars: Vec<u16> = name.encode_utf16().collect();
+    if chars.len() > FAT_LONG_NAME_MAX
+        || matches!(name, "" | "." | "..")
+        || name.ends_with(['.', ' '])
+        || name
+            .chars()
+            .any(|c| c.is_ascii_control() || FAT_LONG_NAME_INVALID.contains(c))
+    {
+        return None;
+    }
+
+    let count = chars.len().div_ceil(FAT_LONG_NAME_CHARS);
+    let items = (0..count).rev().map(|i| {
+        // The name ends with a NUL if there is room for one, what is left after it is 0xFFFF
+        let mut piece = [0xffff; FAT_LONG_NAME_CHARS];
+        let part = &chars[i * FAT_LONG_NAME_CHARS..chars.len().min((i + 1) * FAT_LONG_NAME_CHARS)];
+        piece[..part.len()].copy_from_slice(part);
+        if part.len() < FAT_LONG_NAME_CHARS {
+            piece[part.len()] = 0;
+        }
+
+        let last = if i + 1 == count {
+            FAT_LONG_NAME_LAST
+        } else {
+            0
+        };
+        FatLongNameItem {
+            order: (i as u8 + 1) | last,
+            name1: piece[..5].try_into().unwrap(),
+            attributes: FAT_FILE_LONG_NAME,
+            checksum,
+            name2: piece[5..11].try_into().unwrap(),
+            name3: piece[11..].try_into().unwrap(),
+            ..Default::default()
         }
+    });
+
+    Some(items.collect())
+}
+
+/// The long name the pieces in @items make, in the order they were on disk.
+/// None if they don't make a whole name for the item with @checksum.
+fn long_name(items: &[FatLongNameItem], checksum: u8) -> Option<String> {
+    let first = items.first()?;
+    if first.order & FAT_LONG_NAME_LAST == 0
+        || (first.order & FAT_LONG_NAME_ORDER) as usize != items.len()
+    {
+        return None;
+    }
 
-        None
+    let in_order = items.iter().rev().enumerate().all(|(i, item)| {
+        (item.order & FAT_LONG_NAME_ORDER) as usize == i + 1 && item.checksum == checksum
+    });
+    if !in_order {
+        return None;
     }
+
+    let chars = items
+        .iter()
+        .rev()
+        .flat_map(|item| item.chars())
+        .take_while(|&c| c != 0);
+
+    Some(
+        char::decode_utf16(chars)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect(),
+    )
 }
 
-pub(super) enum FatItem {
-    Directory(FatDirectory),
-    File(FatDirectoryItem),
+/// An entry in use and where on disk it is
+#[derive(Clone)]
+pub(super) struct FatEntry {
+    pub item: FatDirectoryItem,
+    pub offset: usize,
+    pub name: String,    // The long name if it has one, the short one otherwise
+    pub lfn: Vec<usize>, // Where the pieces of its long name are
+}
+
+impl FatEntry {
+    /// Whether the entry is called @name by its long or its short name.
+    /// Case doesn't matter, for ASCII letters at least.
+    pub fn name_matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.item.short_name().eq_ignore_ascii_case(name)
+    }
+
+    /// Only the attributes readdir knows about are kept
+    pub fn dir_entry(&self) -> DirEntry {
+        let item = &self.item;
+        let attributes = [
+            (FAT_FILE_READ_ONLY, DIRENT_READ_ONLY),
+            (FAT_FILE_HIDDEN, DIRENT_HIDDEN),
+            (FAT_FILE_SYSTEM, DIRENT_SYSTEM),
+            (FAT_FILE_SUBDIRECTORY, DIRENT_DIRECTORY),
+            (FAT_FILE_ARCHIVED, DIRENT_ARCHIVED),
+        ]
+        .into_iter()
+        .filter(|&(fat, _)| item.attributes & fat != 0)
+        .fold(0, |attributes, (_, bit)| attributes | bit);
+
+        DirEntry {
+            name: self.name.clone(),
+            attributes,
+            size: item.filesize as usize,
+            created: from_fat(item.creation_dat, item.creation_time),
+            modified: from_fat(item.last_mod_data, item.last_mod_time),
+        }
+    }
 }
 
-impl FatItem {
-    pub fn new(_stream: &mut dyn Stream, item: &FatDirectoryItem) -> Self {
-        match item.attributes {
-            FAT_FILE_SUBDIRECTORY => {
-                // let size = FatDirectoryItem::size(stream);
-                // FatItem::Directory(FatDirectory::new(stream, item.first_cluster(), size))
-                todo!()
+pub(super) struct FatDirectory {
+    items: Vec<FatEntry>, // The entries in use, in the order they are on disk
+    pub cluster: usize,   // Where it starts, 0 for the root directory
+}
+
+impl FatDirectory {
+    /// The directory whose entries are in the cluster chain from @cluster on.
+    /// Cluster 0 is the root directory, which is where `..` of its subdirectories points.
+    pub fn load(fs: &Fat16, stream: &mut dyn Stream, cluster: usize) -> Self {
+        let mut items = Vec::new();
+        // The pieces of a long name seen so far, and where they are
+        let mut pieces: Vec<FatLongNameItem> = Vec::new();
+        let mut lfn: Vec<usize> = Vec::new();
+
+        for offset in fs.slots(stream, cluster) {
+            stream.seek(Offset(offset));
+            let item = FatDirectoryItem::new(stream);
+            match item.filename[0] {
+                FAT_ENTRY_END => break,
+                FAT_ENTRY_DELETED => {}
+                _ if item.is_long_name() => {
+                    pieces.push(FatLongNameItem::from_bytes(item.as_bytes()));
+                    lfn.push(offset);
+                    continue;
+                }
+                _ if item.is_volume_label() => {}
+                _ => {
+                    // Pieces left over from a name that was changed by something not knowing
+                    // about long names don't belong to this item
+                    let (name, lfn) = match long_name(&pieces, item.checksum()) {
+                        Some(name) => (name, core::mem::take(&mut lfn)),
+                        None => (item.short_name(), Vec::new()),
+                    };
+                    items.push(FatEntry {
+                        item,
+                        offset,
+                        name,
+                        lfn,
+                    });
+                }
             }
-            _ => FatItem::File(*item),
+
+            pieces.clear();
+            lfn.clear();
         }
+
+        Self { items, cluster }
+    }
+
+    pub fn entries(&self) -> &[FatEntry] {
+        &self.items
+    }
+
+    pub fn find(&self, name: &str) -> Option<FatEntry> {
+        self.items
+            .iter()
+            .find(|entry| entry.name_matches(name))
+            .cloned()
+    }
+
+    /// A short name for @name no entry has yet, like `LONGNA~1.TXT` for `long name.txt`
+    pub fn short_alias(&self, name: &str) -> Option<String> {
+        let short = |part: &str, max| -> String {
+            part.chars()
+                .filter(|&c| !matches!(c, ' ' | '.'))
+                .map(|c| match c.to_ascii_uppercase() {
+                    c if c.is_ascii_graphic() && !FAT_NAME_INVALID.contains(&(c as u8)) => c,
+                    _ => '_',
+                })
+                .take(max)
+                .collect()
+        };
+
+        let name = name.trim_start_matches('.');
+        let (base, ext) = match name.rsplit_once('.') {
+            Some((base, ext)) => (short(base, 8), short(ext, 3)),
+            None => (short(name, 8), String::new()),
+        };
+
+        (1..1_000_000).find_map(|n: usize| {
+            let tail = format!("~{}", n);
+            let base = &base[..base.len().min(8 - tail.len())];
+            let alias = match ext.as_str() {
+                "" => format!("{}{}", base, tail),
+                ext => format!("{}{}.{}", base, tail, ext),
+            };
+
+            let taken = self
+                .items
+                .iter()
+                .any(|entry| entry.item.short_name().eq_ignore_ascii_case(&alias));
+            (!taken).then_some(alias)
+        })
     }
 }
@@ -1,55 +1,205 @@
 mod r#impl;
 mod private;
+mod write;
 use crate::{
     boxed::{Array, Box, Dyn},
     disk::{Disk, Offset, Sector, Stream},
-    fs::{FileDescriptor, FileMode, FileStat, FileSystem, IOError},
+    fs::{DirEntry, FileDescriptor, FileMode, FileStat, FileSystem, IOError},
     path::Path,
-    sync::Global,
+    rtc::DateTime,
+    sync::{mutex::Mutex, Global, Shared, Weak},
 };
+use alloc::{string::String, vec::Vec};
 use core::cell::Cell;
 
-use private::{FatDirectoryItem, FatH};
-use r#impl::{FatDirectory, FatItem, FAT16_SIGNATURE};
+use private::{FatDirectoryItem, FatH, FAT_DIRECTORY_ITEM_SIZE};
+use r#impl::{
+    FatDirectory, FatEntry, FAT16_BAD_CLUSTER, FAT16_END_OF_CHAIN, FAT16_ENTRY_SIZE,
+    FAT16_FREE_CLUSTER, FAT16_SIGNATURE,
+};
 
 pub struct Fat16 {
     disk_id: u32,
     header: FatH,
-    root_dir: FatDirectory,
+    open: Mutex<Vec<Weak<FatEntry>>>, // The entries of open files, descriptors share them
 }
 
 impl Fat16 {
-    pub(self) fn new(disk_id: u32, header: FatH, root_dir: FatDirectory) -> Self {
+    pub(self) fn new(disk_id: u32, header: FatH) -> Self {
         Self {
             disk_id,
             header,
-            root_dir,
+            open: Mutex::new(Vec::new()),
         }
     }
 
-    fn root(&self) -> &FatDirectory {
-        &self.root_dir
+    /// The entry the descriptors of the file at @entry share, @entry if it isn't open yet
+    fn share(&self, entry: FatEntry) -> Shared<FatEntry> {
+        let mut open = self.open.lock();
+        open.retain(|weak| weak.upgrade().is_some());
+
+        if let Some(shared) = open
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|shared| shared.with_rlock(|open| open.offset == entry.offset))
+        {
+            return shared;
+        }
+
+        let shared = Shared::new(entry);
+        open.push(Shared::weak(&shared));
+        shared
+    }
+
+    /// Whether the entry at @offset belongs to a file that is open
+    fn is_open(&self, offset: usize) -> bool {
+        self.open
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .any(|shared| shared.with_rlock(|open| open.offset == offset))
     }
 
-    fn get_directory_entry(&self, stream: &mut dyn Stream, path: Path) -> Option<FatItem> {
-        let mut iter = path.parts().into_iter();
+    /// Directories are read from disk every time, they may have changed since
+    fn root(&self, stream: &mut dyn Stream) -> FatDirectory {
+        FatDirectory::load(self, stream, 0)
+    }
 
-        let root = self.root();
-        let part = iter.next()?;
+    /// The directory @parts lead to from the root
+    fn directory(&self, stream: &mut dyn Stream, parts: &[&str]) -> Result<FatDirectory, IOError> {
+        let mut dir = self.root(stream);
+
+        for part in parts {
+            dir = match dir.find(part) {
+                Some(entry) if entry.item.is_directory() => {
+                    FatDirectory::load(self, stream, entry.item.first_cluster())
+                }
+                // Only subdirectories have entries for themselves and their parent
+                None if dir.cluster == 0 && matches!(*part, "." | "..") => continue,
+                Some(_) => return Err(IOError::NotADirectory),
+                None => return Err(IOError::NoSuchFile),
+            };
+        }
 
-        let mut current = root.find(stream, part)?;
+        Ok(dir)
+    }
 
-        for next in iter {
-            match current {
-                FatItem::Directory(ref dir) => current = dir.find(stream, next)?,
-                FatItem::File(_) => return None,
-            }
+    /// The directory @path is in and the name it has there
+    fn parent<'a>(
+        &self,
+        stream: &mut dyn Stream,
+        path: &Path<'a>,
+    ) -> Result<(FatDirectory, &'a str), IOError> {
+        let parts: Vec<&str> = path.parts().into_iter().copied().collect();
+        let (name, parents) = parts.split_last().ok_or(IOError::NoSuchFile)?;
+
+        Ok((self.directory(stream, parents)?, name))
+    }
+
+    /// The clusters in the chain from @first on
+    fn chain(&self, stream: &mut dyn Stream, first: usize) -> Vec<usize> {
+        let mut chain = Vec::new();
+
+        let mut next = Some(first).filter(|&cluster| cluster >= 2);
+        while let Some(cluster) = next {
+            chain.push(cluster);
+            next = self.next_cluster(stream, cluster);
+        }
+
+        chain
+    }
+
+    /// Where every entry of the directory at @cluster is on disk, in use or not
+    fn slots(&self, stream: &mut dyn Stream, cluster: usize) -> Vec<usize> {
+        if cluster == 0 {
+            return (0..self.header.root_entries())
+                .map(|i| self.header.root() + i * FAT_DIRECTORY_ITEM_SIZE)
+                .collect();
         }
-        Some(current)
+
+        let per_cluster = self.cluster_size() / FAT_DIRECTORY_ITEM_SIZE;
+        self.chain(stream, cluster)
+            .into_iter()
+            .flat_map(|cluster| {
+                let start = self.cluster_offset(cluster);
+                (0..per_cluster).map(move |i| start + i * FAT_DIRECTORY_ITEM_SIZE)
+            })
+            .collect()
+    }
+
+    /// Where @cluster is on disk, in bytes
+    fn cluster_offset(&self, cluster: usize) -> usize {
+        self.cluster_to_sector(cluster) * self.header.bytes_per_sector()
     }
 
     fn cluster_to_sector(&self, cluster: usize) -> usize {
-        self.root_dir.end + (cluster - 2) * self.header.primary_header.sectors_per_cluster as usize
+        self.header.data_start() + (cluster - 2) * self.header.sectors_per_cluster()
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.header.cluster_size()
+    }
+
+    /// Read from @offset in the file of @item into @buf, following its cluster chain.
+    /// Returns how many bytes were read, fewer than asked for if the chain ends first.
+    fn read(
+        &self,
+        stream: &mut dyn Stream,
+        item: &FatDirectoryItem,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> usize {
+        let cluster_size = self.cluster_size();
+
+        // Empty files have no clusters
+        let mut cluster = Some(item.first_cluster()).filter(|&cluster| cluster >= 2);
+        for _ in 0..offset / cluster_size {
+            cluster = cluster.and_then(|cluster| self.next_cluster(stream, cluster));
+        }
+
+        let mut done = 0;
+        let mut skip = offset % cluster_size;
+        while let Some(current) = cluster.filter(|_| done < buf.len()) {
+            let n = (cluster_size - skip).min(buf.len() - done);
+
+            stream.seek_sector(Sector(self.cluster_to_sector(current)));
+            stream.seek(Offset(stream.pos().0 + skip));
+            stream.read(&mut buf[done..done + n], n);
+
+            done += n;
+            skip = 0;
+            cluster = self.next_cluster(stream, current);
+        }
+
+        done
+    }
+
+    /// Whether the directory at @cluster is the one at @ancestor or somewhere below it
+    fn is_within(&self, stream: &mut dyn Stream, mut cluster: usize, ancestor: usize) -> bool {
+        while cluster != 0 {
+            if cluster == ancestor {
+                return true;
+            }
+            cluster = FatDirectory::load(self, stream, cluster)
+                .find("..")
+                .map_or(0, |parent| parent.item.first_cluster());
+        }
+
+        false
+    }
+
+    /// The cluster after @cluster in its chain, None at the end of it
+    fn next_cluster(&self, stream: &mut dyn Stream, cluster: usize) -> Option<usize> {
+        let mut buf = [0; FAT16_ENTRY_SIZE];
+        stream.seek(Offset(self.header.fat_start() + cluster * FAT16_ENTRY_SIZE));
+        stream.read(&mut buf, FAT16_ENTRY_SIZE);
+
+        // Free and reserved clusters shouldn't be in a chain, so they end it too
+        match u16::from_le_bytes(buf) {
+            FAT16_FREE_CLUSTER | FAT16_BAD_CLUSTER => None,
+            next if (2..FAT16_END_OF_CHAIN).contains(&next) => Some(next as usize),
+            _ => None,
+        }
     }
 }
 
@@ -62,17 +212,7 @@ impl FileSystem for Fat16 {
             return Err(FSError::NotOurFS);
         }
 
-        let root_start = header.root();
-
-        let root_dir = disk.with_rlock(|disk| -> FatDirectory {
-            FatDirectory::new(
-                &mut disk.stream(),
-                Offset(root_start),
-                header.primary_header.root_dir_entries as usize,
-            )
-        });
-
-        let fs = Dyn::new(Self::new(id, header, root_dir));
+        let fs = Dyn::new(Self::new(id, header));
         disk.with_wlock(|disk| disk.register_filesystem(fs));
 
         Ok(())
@@ -84,21 +224,126 @@ impl FileSystem for Fat16 {
         path: Path,
         mode: FileMode,
     ) -> Result<Box<dyn FileDescriptor>, IOError> {
-        let Some(entry) = self.get_directory_entry(stream, path) else {
-            return Err(IOError::NoSuchFile);
-        };
+        let (dir, name) = self.parent(stream, &path)?;
 
-        let file = match entry {
-            FatItem::Directory(_) => return Err(IOError::NotAFile),
-            FatItem::File(f) => f,
+        let entry = match dir.find(name) {
+            Some(entry) if entry.item.is_directory() => return Err(IOError::NotAFile),
+            Some(entry) if entry.item.is_read_only() && mode.is_writable() => {
+                return Err(IOError::ReadOnly)
+            }
+            Some(entry) => entry,
+            None if mode.contains(FileMode::CREATE) => self.create(stream, &dir, name)?,
+            None => return Err(IOError::NoSuchFile),
         };
 
+        let mut entry = self.share(entry);
+        if mode.contains(FileMode::TRUNCATE) {
+            entry.with_wlock(|entry| -> Result<(), IOError> {
+                if entry.item.filesize != 0 || entry.item.first_cluster() != 0 {
+                    self.truncate(stream, &mut entry.item)?;
+                    entry.item.set_modified(DateTime::now());
+                    self.write_entry(stream, entry)?;
+                }
+
+                Ok(())
+            })?;
+        }
+
         let desc: Box<dyn FileDescriptor> =
-            Box::new(FatFileDescriptor::new(self.disk_id, file, mode));
+            Box::new(FatFileDescriptor::new(self.disk_id, entry, mode));
 
         Ok(desc)
     }
 
+    fn opendir(
+        &self,
+        stream: &mut dyn Stream,
+        path: Path,
+    ) -> Result<Box<dyn FileDescriptor>, IOError> {
+        let parts: Vec<&str> = path.parts().into_iter().copied().collect();
+        let dir = self.directory(stream, &parts)?;
+
+        Ok(Box::new(FatDirDescriptor {
+            dir,
+            pos: Cell::new(0),
+        }))
+    }
+
+    /// Only files can be removed, and not while they are open
+    fn remove(&self, stream: &mut dyn Stream, path: Path) -> Result<(), IOError> {
+        let (dir, name) = self.parent(stream, &path)?;
+        let entry = dir.find(name).ok_or(IOError::NoSuchFile)?;
+
+        if entry.item.is_directory() {
+            return Err(IOError::NotAFile);
+        }
+        if entry.item.is_read_only() {
+            return Err(IOError::ReadOnly);
+        }
+        if self.is_open(entry.offset) {
+            return Err(IOError::Busy);
+        }
+
+        self.delete(stream, &entry)?;
+        self.free_chain(stream, entry.item.first_cluster())?;
+
+        Ok(())
+    }
+
+    fn rename(&self, stream: &mut dyn Stream, from: Path, to: Path) -> Result<(), IOError> {
+        let (from_dir, from_name) = self.parent(stream, &from)?;
+        let old = from_dir
+            .find(from_name)
+            .filter(|entry| !matches!(entry.item.filename(), "." | ".."))
+            .ok_or(IOError::NoSuchFile)?;
+
+        // Its descriptors would write the entry back where it was
+        if self.is_open(old.offset) {
+            return Err(IOError::Busy);
+        }
+
+        // Only changing the case of its name finds the entry itself
+        let (to_dir, to_name) = self.parent(stream, &to)?;
+        if to_dir
+            .find(to_name)
+            .is_some_and(|entry| entry.offset != old.offset)
+        {
+            return Err(IOError::Exists);
+        }
+
+        let item = old.item;
+        let moved = to_dir.cluster != from_dir.cluster;
+        if moved
+            && item.is_directory()
+            && self.is_within(stream, to_dir.cluster, item.first_cluster())
+        {
+            return Err(IOError::InvalidArgument);
+        }
+
+        // The new name may need more slots than the old one had, so it goes in anew
+        self.insert(stream, &to_dir, to_name, item)?;
+        self.delete(stream, &old)?;
+
+        // Its `..` has to point at where it is now
+        if moved && item.is_directory() {
+            let offset = self.cluster_offset(item.first_cluster()) + FAT_DIRECTORY_ITEM_SIZE;
+            stream.seek(Offset(offset));
+            let mut parent = FatDirectoryItem::new(stream);
+            parent.set_first_cluster(to_dir.cluster);
+            self.write_entry(
+                stream,
+                &FatEntry {
+                    item: parent,
+                    offset,
+                    name: String::from(".."),
+                    lfn: Vec::new(),
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
     fn name(&self) -> &'static str {
         "FAT16"
     }
@@ -109,104 +354,191 @@ impl FileSystem for Fat16 {
 }
 
 pub struct FatFileDescriptor {
-    item: FatDirectoryItem,
+    entry: Shared<FatEntry>, // Shared with the other descriptors of the file
     disk_id: u32,
     pos: Cell<usize>,
     mode: FileMode,
 }
 
 impl FatFileDescriptor {
-    fn new(disk_id: u32, item: FatDirectoryItem, mode: FileMode) -> Self {
+    fn new(disk_id: u32, entry: Shared<FatEntry>, mode: FileMode) -> Self {
+        let pos = if mode.contains(FileMode::APPEND) {
+            entry.with_rlock(|entry| entry.item.filesize as usize)
+        } else {
+            0
+        };
+
         Self {
             disk_id,
-            item,
-            pos: Cell::new(0),
+            entry,
+            pos: Cell::new(pos),
             mode,
         }
     }
+
+    fn with_fs<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&Fat16, &mut dyn Stream) -> T,
+    {
+        Disk::get_mut(self.disk_id).with_rlock(|disk| {
+            let fs = disk
+                .filesystem
+                .as_ref()
+                .unwrap()
+                .as_any()
+                .downcast_ref::<Fat16>()
+                .expect("A FAT16 filesystem");
+
+            f(fs, &mut disk.stream())
+        })
+    }
+
+    /// At most @size bytes from @offset on, fewer at the end of the file
+    fn read_at(&self, offset: usize, size: usize) -> Array<u8> {
+        let size = size.min(self.stat().size.saturating_sub(offset));
+
+        let mut buf = Array::new(size);
+        let read = self.with_fs(|fs, stream| {
+            self.entry
+                .with_rlock(|entry| fs.read(stream, &entry.item, offset, &mut buf))
+        });
+        if read == size {
+            return buf;
+        }
+
+        // The chain ended before the file did
+        let mut short = Array::new(read);
+        short.copy_from_slice(&buf[..read]);
+        buf.free();
+
+        short
+    }
 }
 
 use crate::fs::{FSError, SeekMode};
 impl FileDescriptor for FatFileDescriptor {
+    /// Reads stop short at the end of the file
     fn read(&self, size: usize) -> Result<Array<u8>, IOError> {
-        if size > self.stat().size {
+        let buf = self.read_at(self.pos.get(), size);
+        self.pos.set(self.pos.get() + buf.len());
+
+        Ok(buf)
+    }
+
+    fn read_all(&self) -> Result<Array<u8>, IOError> {
+        Ok(self.read_at(0, self.stat().size))
+    }
+
+    /// Files grow as needed, appends always go at the end
+    fn write(&mut self, size: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        if !self.mode.is_writable() {
             return Err(IOError::InvalidArgument);
         }
 
-        let mut buf = Array::new(size);
+        let len = size.checked_mul(count).ok_or(IOError::InvalidArgument)?;
+        let buf = buf.get(..len).ok_or(IOError::InvalidArgument)?;
+        let append = self.mode.contains(FileMode::APPEND);
+        let pos = self.pos.get();
+
+        // The end of the file is found with the entry locked, other descriptors may append too
+        let mut entry = self.entry.clone();
+        let offset = self.with_fs(|fs, stream| {
+            entry.with_wlock(|entry| {
+                let offset = if append {
+                    entry.item.filesize as usize
+                } else {
+                    pos
+                };
+                let written = fs.write(stream, &mut entry.item, offset, buf);
+                entry.item.set_modified(DateTime::now());
+                written.and(fs.write_entry(stream, entry)).map(|()| offset)
+            })
+        })?;
+
+        self.pos.set(offset + len);
+        Ok(())
+    }
 
-        Disk::get_mut(self.disk_id).with_rlock(|disk| {
-            let mut stream = disk.stream();
-
-            if self.pos.get() == 0 {
-                let fs = disk
-                    .filesystem
-                    .as_ref()
-                    .unwrap()
-                    .as_any()
-                    .downcast_ref::<Fat16>()
-                    .expect("A FAT16 filesystem");
-
-                let start_sector = fs.cluster_to_sector(self.item.first_cluster());
-                stream.seek_sector(Sector(start_sector));
-            }
+    fn seek(&self, offset: isize, whence: SeekMode) -> Result<usize, IOError> {
+        let base = match whence {
+            SeekMode::CurrentPosition => self.pos.get() as isize,
+            SeekMode::EndOfFile => self.stat().size as isize,
+            SeekMode::StartOfFile => 0,
+        };
 
-            stream.seek(Offset(self.pos.get()));
-            stream.read(&mut buf, size);
-            self.pos.set(stream.pos().0);
-        });
+        let pos = base + offset;
+        if pos < 0 {
+            return Err(IOError::InvalidArgument);
+        }
 
-        Ok(buf)
+        self.pos.set(pos as usize);
+        Ok(pos as usize)
     }
 
-    fn read_all(&self) -> Result<Array<u8>, IOError> {
-        let size = self.stat().size;
-        let mut buf = Array::new(size);
-
-        Disk::get_mut(self.disk_id).with_rlock(|disk| {
-            let mut stream = disk.stream();
+    fn stat(&self) -> FileStat {
+        FileStat {
+            mode: self.mode,
+            size: self.entry.with_rlock(|entry| entry.item.filesize as usize),
+        }
+    }
 
-            let fs = disk
-                .filesystem
-                .as_ref()
-                .unwrap()
-                .as_any()
-                .downcast_ref::<Fat16>()
-                .expect("A FAT16 filesystem");
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
 
-            let start_sector = fs.cluster_to_sector(self.item.first_cluster());
+/// The entries are read when the directory is opened
+pub struct FatDirDescriptor {
+    dir: FatDirectory,
+    pos: Cell<usize>, // The next entry readdir gives
+}
 
-            stream.seek_sector(Sector(start_sector));
-            stream.read(&mut buf, size);
-        });
+impl FileDescriptor for FatDirDescriptor {
+    fn read(&self, _size: usize) -> Result<Array<u8>, IOError> {
+        Err(IOError::NotAFile)
+    }
 
-        Ok(buf)
+    fn read_all(&self) -> Result<Array<u8>, IOError> {
+        Err(IOError::NotAFile)
     }
 
     fn write(&mut self, _size: usize, _count: usize, _buf: &[u8]) -> Result<(), IOError> {
-        todo!()
+        Err(IOError::NotAFile)
     }
 
-    fn seek(&self, offset: isize, whence: SeekMode) {
-        match whence {
-            SeekMode::CurrentPosition => self.pos.set((self.pos.get() as isize + offset) as usize),
-            SeekMode::EndOfFile => self
-                .pos
-                .set((self.item.filesize as isize - offset) as usize),
-            SeekMode::StartOfFile => self.pos.set(offset as usize),
+    /// Positions count entries, not bytes
+    fn seek(&self, offset: isize, whence: SeekMode) -> Result<usize, IOError> {
+        let base = match whence {
+            SeekMode::CurrentPosition => self.pos.get() as isize,
+            SeekMode::EndOfFile => self.dir.entries().len() as isize,
+            SeekMode::StartOfFile => 0,
+        };
+
+        let pos = base + offset;
+        if pos < 0 {
+            return Err(IOError::InvalidArgument);
         }
+
+        self.pos.set(pos as usize);
+        Ok(pos as usize)
     }
 
     fn stat(&self) -> FileStat {
-        let descriptor = self.as_any().downcast_ref::<FatFileDescriptor>().unwrap();
-        let size = descriptor.item.filesize;
-        let mode = descriptor.mode;
         FileStat {
-            mode,
-            size: size as usize,
+            mode: FileMode::READ_ONLY,
+            size: self.dir.entries().len(),
         }
     }
 
+    fn readdir(&self) -> Result<Option<DirEntry>, IOError> {
+        let Some(entry) = self.dir.entries().get(self.pos.get()) else {
+            return Ok(None);
+        };
+
+        self.pos.set(self.pos.get() + 1);
+        Ok(Some(entry.dir_entry()))
+    }
+
     fn as_any(&self) -> &dyn core::any::Any {
         self
     }
@@ -43,6 +43,60 @@ impl FatH {
             + primary_header.reserved_sectors as usize)
             * 512
     }
+
+    /// Where the first copy of the FAT starts, in bytes
+    pub fn fat_start(&self) -> usize {
+        let primary_header = self.primary_header;
+
+        primary_header.reserved_sectors as usize * primary_header.bytes_per_sector as usize
+    }
+
+    pub fn fat_copies(&self) -> usize {
+        self.primary_header.fat_copies as usize
+    }
+
+    /// How big each copy of the FAT is, in bytes
+    pub fn fat_size(&self) -> usize {
+        let primary_header = self.primary_header;
+
+        primary_header.sectors_per_fat as usize * primary_header.bytes_per_sector as usize
+    }
+
+    pub fn root_entries(&self) -> usize {
+        self.primary_header.root_dir_entries as usize
+    }
+
+    /// The first sector after the root directory, where cluster 2 is
+    pub fn data_start(&self) -> usize {
+        let primary_header = self.primary_header;
+
+        (self.root() + self.root_entries() * FAT_DIRECTORY_ITEM_SIZE)
+            .div_ceil(primary_header.bytes_per_sector as usize)
+    }
+
+    pub fn bytes_per_sector(&self) -> usize {
+        self.primary_header.bytes_per_sector as usize
+    }
+
+    pub fn sectors_per_cluster(&self) -> usize {
+        self.primary_header.sectors_per_cluster as usize
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster() * self.primary_header.bytes_per_sector as usize
+    }
+
+    /// How many clusters there is room for on the disk, the first one is cluster 2
+    pub fn total_clusters(&self) -> usize {
+        let primary_header = self.primary_header;
+        let sectors = match primary_header.number_of_sectors {
+            0 => primary_header.sectors_big as usize,
+            sectors => sectors as usize,
+        };
+
+        (sectors.saturating_sub(self.data_start()) / self.sectors_per_cluster())
+            .min(self.fat_size() / 2 - 2)
+    }
 }
 
 pub const _FAT_HEADER_SIZE: usize = core::mem::size_of::<FatH>();
@@ -82,9 +136,33 @@ impl FatDirectoryItem {
     pub fn extension(&self) -> &str {
         core::str::from_utf8(&self.extension).unwrap_or("").trim()
     }
+
+    pub fn as_bytes(&self) -> &[u8; FAT_DIRECTORY_ITEM_SIZE] {
+        unsafe { &*(self as *const FatDirectoryItem as *const [u8; FAT_DIRECTORY_ITEM_SIZE]) }
+    }
 }
 
 pub const FAT_DIRECTORY_ITEM_SIZE: usize = core::mem::size_of::<FatDirectoryItem>();
+
+/// A piece of a VFAT long name, in a slot of its own before the item it names
+#[packed]
+pub struct FatLongNameItem {
+    pub order: u8,
+    pub name1: [u16; 5],
+    pub attributes: u8,
+    pub kind: u8,
+    pub checksum: u8,
+    pub name2: [u16; 6],
+    pub first_cluster: u16,
+    pub name3: [u16; 2],
+}
+
+impl FatLongNameItem {
+    pub fn as_bytes(&self) -> &[u8; FAT_DIRECTORY_ITEM_SIZE] {
+        unsafe { &*(self as *const FatLongNameItem as *const [u8; FAT_DIRECTORY_ITEM_SIZE]) }
+    }
+}
+
 impl From<&[u8; FAT_DIRECTORY_ITEM_SIZE]> for FatDirectoryItem {
     fn from(bytes: &[u8; FAT_DIRECTORY_ITEM_SIZE]) -> Self {
         unsafe { *(bytes.as_ptr() as *const FatDirectoryItem) }
@@ -0,0 +1,274 @@
+use crate::{
+    disk::{Offset, Sector, Stream},
+    fs::IOError,
+    rtc::DateTime,
+};
+use alloc::{string::String, vec, vec::Vec};
+
+use super::private::{FatDirectoryItem, FAT_DIRECTORY_ITEM_SIZE};
+use super::r#impl::{
+    long_name_items, FatDirectory, FatEntry, FAT16_ENTRY_SIZE, FAT16_FREE_CLUSTER,
+    FAT16_LAST_CLUSTER, FAT_ENTRY_DELETED, FAT_ENTRY_END,
+};
+use super::Fat16;
+
+impl Fat16 {
+    /// Set what follows @cluster in every copy of the FAT
+    fn set_cluster(
+        &self,
+        stream: &mut dyn Stream,
+        cluster: usize,
+        next: u16,
+    ) -> Result<(), IOError> {
+        for copy in 0..self.header.fat_copies() {
+            let offset = self.header.fat_start() + copy * self.header.fat_size();
+            stream.seek(Offset(offset + cluster * FAT16_ENTRY_SIZE));
+            stream.write(&next.to_le_bytes(), FAT16_ENTRY_SIZE)?;
+        }
+
+        Ok(())
+    }
+
+    /// A free cluster, zeroed and put at the end of the chain after @prev if there is one
+    fn alloc_cluster(
+        &self,
+        stream: &mut dyn Stream,
+        prev: Option<usize>,
+    ) -> Result<usize, IOError> {
+        let end = self.header.total_clusters() + 2;
+        let per_sector = self.header.bytes_per_sector() / FAT16_ENTRY_SIZE;
+        let mut buf = vec![0; per_sector * FAT16_ENTRY_SIZE];
+
+        // A sector of the FAT at a time, reading an entry reads its whole sector anyway
+        let mut found = None;
+        for first in (0..end).step_by(per_sector) {
+            stream.seek(Offset(self.header.fat_start() + first * FAT16_ENTRY_SIZE));
+            stream.read(&mut buf, per_sector * FAT16_ENTRY_SIZE);
+
+            found = buf
+                .chunks_exact(FAT16_ENTRY_SIZE)
+                .enumerate()
+                .map(|(i, entry)| (first + i, u16::from_le_bytes([entry[0], entry[1]])))
+                .find(|&(cluster, entry)| {
+                    (2..end).contains(&cluster) && entry == FAT16_FREE_CLUSTER
+                })
+                .map(|(cluster, _)| cluster);
+            if found.is_some() {
+                break;
+            }
+        }
+        let cluster = found.ok_or(IOError::NoSpace)?;
+
+        self.set_cluster(stream, cluster, FAT16_LAST_CLUSTER)?;
+        if let Some(prev) = prev {
+            self.set_cluster(stream, prev, cluster as u16)?;
+        }
+
+        let zeroes = vec![0; self.cluster_size()];
+        stream.seek_sector(Sector(self.cluster_to_sector(cluster)));
+        stream.write(&zeroes, zeroes.len())?;
+
+        Ok(cluster)
+    }
+
+    /// The cluster after @cluster, one is added to the chain if it ends there
+    fn next_or_alloc(&self, stream: &mut dyn Stream, cluster: usize) -> Result<usize, IOError> {
+        match self.next_cluster(stream, cluster) {
+            Some(next) => Ok(next),
+            None => self.alloc_cluster(stream, Some(cluster)),
+        }
+    }
+
+    /// Give the clusters in the chain from @first on back
+    pub(super) fn free_chain(&self, stream: &mut dyn Stream, first: usize) -> Result<(), IOError> {
+        for cluster in self.chain(stream, first) {
+            self.set_cluster(stream, cluster, FAT16_FREE_CLUSTER)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write @buf at @offset in the file of @item, its chain grows as needed.
+    /// The size of @item covers what made it to disk, even when we run out of space.
+    pub(super) fn write(
+        &self,
+        stream: &mut dyn Stream,
+        item: &mut FatDirectoryItem,
+        offset: usize,
+        buf: &[u8],
+    ) -> Result<(), IOError> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let mut done = 0;
+        let result = self.write_clusters(stream, item, offset, buf, &mut done);
+        if done != 0 {
+            item.filesize = item.filesize.max((offset + done) as u32);
+        }
+
+        result
+    }
+
+    fn write_clusters(
+        &self,
+        stream: &mut dyn Stream,
+        item: &mut FatDirectoryItem,
+        offset: usize,
+        buf: &[u8],
+        done: &mut usize,
+    ) -> Result<(), IOError> {
+        let cluster_size = self.cluster_size();
+
+        let mut cluster = match item.first_cluster() {
+            0 => {
+                let cluster = self.alloc_cluster(stream, None)?;
+                item.set_first_cluster(cluster);
+                cluster
+            }
+            cluster => cluster,
+        };
+        for _ in 0..offset / cluster_size {
+            cluster = self.next_or_alloc(stream, cluster)?;
+        }
+
+        let mut skip = offset % cluster_size;
+        loop {
+            let n = (cluster_size - skip).min(buf.len() - *done);
+
+            stream.seek(Offset(self.cluster_offset(cluster) + skip));
+            stream.write(&buf[*done..*done + n], n)?;
+
+            *done += n;
+            skip = 0;
+            if *done == buf.len() {
+                return Ok(());
+            }
+            cluster = self.next_or_alloc(stream, cluster)?;
+        }
+    }
+
+    /// Empty the file of @item
+    pub(super) fn truncate(
+        &self,
+        stream: &mut dyn Stream,
+        item: &mut FatDirectoryItem,
+    ) -> Result<(), IOError> {
+        self.free_chain(stream, item.first_cluster())?;
+        item.set_first_cluster(0);
+        item.filesize = 0;
+
+        Ok(())
+    }
+
+    pub(super) fn write_entry(
+        &self,
+        stream: &mut dyn Stream,
+        entry: &FatEntry,
+    ) -> Result<(), IOError> {
+        stream.seek(Offset(entry.offset));
+        stream.write(entry.item.as_bytes(), FAT_DIRECTORY_ITEM_SIZE)?;
+
+        Ok(())
+    }
+
+    /// Mark @entry and the pieces of its long name as not in use, its clusters are left alone
+    pub(super) fn delete(&self, stream: &mut dyn Stream, entry: &FatEntry) -> Result<(), IOError> {
+        for &offset in entry.lfn.iter().chain([&entry.offset]) {
+            stream.seek(Offset(offset));
+            stream.write(&[FAT_ENTRY_DELETED], 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// An empty file called @name in @dir
+    pub(super) fn create(
+        &self,
+        stream: &mut dyn Stream,
+        dir: &FatDirectory,
+        name: &str,
+    ) -> Result<FatEntry, IOError> {
+        let mut item = FatDirectoryItem::default();
+        item.set_created(DateTime::now());
+
+        self.insert(stream, dir, name, item)
+    }
+
+    /// Put @item in @dir as @name. Names that aren't uppercase 8.3 get a long name,
+    /// with a short one made up from it.
+    pub(super) fn insert(
+        &self,
+        stream: &mut dyn Stream,
+        dir: &FatDirectory,
+        name: &str,
+        mut item: FatDirectoryItem,
+    ) -> Result<FatEntry, IOError> {
+        let pieces = match item.set_name(name) {
+            Some(()) => Vec::new(),
+            None => {
+                let alias = dir.short_alias(name).ok_or(IOError::Exists)?;
+                item.set_name(&alias).ok_or(IOError::InvalidArgument)?;
+                long_name_items(name, item.checksum()).ok_or(IOError::InvalidArgument)?
+            }
+        };
+
+        let mut slots = self.free_slots(stream, dir.cluster, pieces.len() + 1)?;
+        let offset = slots.pop().unwrap();
+        for (piece, &offset) in pieces.iter().zip(&slots) {
+            stream.seek(Offset(offset));
+            stream.write(piece.as_bytes(), FAT_DIRECTORY_ITEM_SIZE)?;
+        }
+
+        let entry = FatEntry {
+            item,
+            offset,
+            name: String::from(name),
+            lfn: slots,
+        };
+        self.write_entry(stream, &entry)?;
+
+        Ok(entry)
+    }
+
+    /// Where @count entries in a row can go in the directory at @cluster.
+    /// Subdirectories grow when they are full, the root directory can't.
+    fn free_slots(
+        &self,
+        stream: &mut dyn Stream,
+        cluster: usize,
+        count: usize,
+    ) -> Result<Vec<usize>, IOError> {
+        let mut run = Vec::new();
+        let mut first = [0];
+        for offset in self.slots(stream, cluster) {
+            stream.seek(Offset(offset));
+            stream.read(&mut first, 1);
+            if !matches!(first[0], FAT_ENTRY_END | FAT_ENTRY_DELETED) {
+                run.clear();
+                continue;
+            }
+
+            run.push(offset);
+            if run.len() == count {
+                return Ok(run);
+            }
+        }
+
+        let mut last = match self.chain(stream, cluster).last() {
+            Some(&last) if cluster != 0 => last,
+            _ => return Err(IOError::NoSpace),
+        };
+
+        // A new cluster is zeroed, so every entry in it is free and the run goes on into it
+        let per_cluster = self.cluster_size() / FAT_DIRECTORY_ITEM_SIZE;
+        while run.len() < count {
+            last = self.alloc_cluster(stream, Some(last))?;
+            let start = self.cluster_offset(last);
+            let needed = count - run.len();
+            run.extend((0..per_cluster.min(needed)).map(|i| start + i * FAT_DIRECTORY_ITEM_SIZE));
+        }
+
+        Ok(run)
+    }
+}
@@ -2,10 +2,18 @@ use crate::{
     boxed::{Array, Box},
     disk::{Disk, Stream},
     path::Path,
+    rtc::DateTime,
     sync::Global,
+    syscalls::{
+        errno::{
+            EACCES, EAGAIN, EBUSY, EEXIST, EINVAL, EIO, EISDIR, ENODEV, ENOENT, ENOSPC, ENOTDIR,
+        },
+        DIRENT_DIRECTORY,
+    },
 };
+use alloc::string::String;
 
-use core::any::Any;
+use core::{any::Any, ops::BitOr};
 
 mod filesystems;
 use filesystems::fat16::Fat16;
@@ -22,12 +30,66 @@ pub enum IOError {
     NoSuchFile,
     NoFS,
     NotAFile,
+    NotADirectory,
     InvalidArgument,
+    WouldBlock, // Nothing to read yet
+    NoSpace,
+    Exists,
+    ReadOnly, // The file may not be written to
+    Busy,     // The file is open
+    Device,   // The disk failed to do what it was told
 }
 
-#[derive(Clone, Copy)]
-pub enum FileMode {
-    ReadOnly,
+impl IOError {
+    pub fn errno(&self) -> usize {
+        match self {
+            IOError::InvalidDisk | IOError::NoFS => ENODEV,
+            IOError::NoSuchFile => ENOENT,
+            IOError::NotAFile => EISDIR,
+            IOError::NotADirectory => ENOTDIR,
+            IOError::InvalidArgument => EINVAL,
+            IOError::WouldBlock => EAGAIN,
+            IOError::NoSpace => ENOSPC,
+            IOError::Exists => EEXIST,
+            IOError::ReadOnly => EACCES,
+            IOError::Busy => EBUSY,
+            IOError::Device => EIO,
+        }
+    }
+}
+
+impl From<crate::disk::IOError> for IOError {
+    fn from(_: crate::disk::IOError) -> Self {
+        IOError::Device
+    }
+}
+
+/// How a file is opened, APPEND, TRUNCATE and CREATE go with WRITE_ONLY and each other
+#[derive(Clone, Copy, PartialEq)]
+pub struct FileMode(u8);
+
+impl FileMode {
+    pub const READ_ONLY: Self = Self(0);
+    pub const WRITE_ONLY: Self = Self(1 << 0); // Writes over what is there
+    pub const APPEND: Self = Self(1 << 1); // Every write goes at the end
+    pub const TRUNCATE: Self = Self(1 << 2); // The file is emptied when it is opened
+    pub const CREATE: Self = Self(1 << 3); // The file is made if there is none
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.contains(FileMode::WRITE_ONLY)
+    }
+}
+
+impl BitOr for FileMode {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
 }
 
 pub struct FileStat {
@@ -35,6 +97,29 @@ pub struct FileStat {
     pub size: usize,
 }
 
+/// What readdir says about an entry of a directory
+pub struct DirEntry {
+    pub name: String,    // With the extension
+    pub attributes: u32, // DIRENT_* bits, getdents hands them out as they are
+    pub size: usize,
+    pub created: DateTime,
+    pub modified: DateTime,
+}
+
+impl DirEntry {
+    /// What comes after the last dot in the name, if anything
+    pub fn extension(&self) -> &str {
+        match self.name.rsplit_once('.') {
+            Some((base, ext)) if !base.is_empty() => ext,
+            _ => "",
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes & DIRENT_DIRECTORY != 0
+    }
+}
+
 pub enum SeekMode {
     StartOfFile,
     CurrentPosition,
@@ -51,6 +136,15 @@ pub trait FileSystem {
         path: Path,
         _mode: FileMode,
     ) -> Result<Box<dyn FileDescriptor>, IOError>;
+    /// A descriptor to readdir the directory at @path with
+    fn opendir(
+        &self,
+        stream: &mut dyn Stream,
+        path: Path,
+    ) -> Result<Box<dyn FileDescriptor>, IOError>;
+    fn remove(&self, stream: &mut dyn Stream, path: Path) -> Result<(), IOError>;
+    /// @to must be on the same filesystem and not exist yet
+    fn rename(&self, stream: &mut dyn Stream, from: Path, to: Path) -> Result<(), IOError>;
     fn name(&self) -> &'static str;
     fn as_any(&self) -> &dyn Any;
 }
@@ -59,8 +153,13 @@ pub trait FileDescriptor {
     fn read(&self, size: usize) -> Result<Array<u8>, IOError>;
     fn read_all(&self) -> Result<Array<u8>, IOError>;
     fn write(&mut self, size: usize, count: usize, buf: &[u8]) -> Result<(), IOError>;
-    fn seek(&self, offset: isize, whence: SeekMode);
+    /// Returns the new position from the start of the file
+    fn seek(&self, offset: isize, whence: SeekMode) -> Result<usize, IOError>;
     fn stat(&self) -> FileStat;
+    /// The next entry of a directory, None after the last one
+    fn readdir(&self) -> Result<Option<DirEntry>, IOError> {
+        Err(IOError::NotADirectory)
+    }
     fn as_any(&self) -> &dyn Any;
 }
 
@@ -86,4 +185,50 @@ impl VFS {
             fs.open(&mut stream, path, mode)
         })
     }
+
+    pub fn opendir(path: Path) -> Result<Box<dyn FileDescriptor>, IOError> {
+        let Some(disk_id) = path.disk_id else {
+            return Err(IOError::InvalidDisk);
+        };
+
+        Disk::get_mut(disk_id).with_rlock(|disk| {
+            let Some(ref fs) = disk.filesystem else {
+                return Err(IOError::NoFS);
+            };
+
+            fs.opendir(&mut disk.stream(), path)
+        })
+    }
+
+    pub fn remove(path: Path) -> Result<(), IOError> {
+        let Some(disk_id) = path.disk_id else {
+            return Err(IOError::InvalidDisk);
+        };
+
+        Disk::get_mut(disk_id).with_rlock(|disk| {
+            let Some(ref fs) = disk.filesystem else {
+                return Err(IOError::NoFS);
+            };
+
+            fs.remove(&mut disk.stream(), path)
+        })
+    }
+
+    /// Both paths have to be on the same disk
+    pub fn rename(from: Path, to: Path) -> Result<(), IOError> {
+        let (Some(disk_id), Some(to_disk)) = (from.disk_id, to.disk_id) else {
+            return Err(IOError::InvalidDisk);
+        };
+        if disk_id != to_disk {
+            return Err(IOError::InvalidArgument);
+        }
+
+        Disk::get_mut(disk_id).with_rlock(|disk| {
+            let Some(ref fs) = disk.filesystem else {
+                return Err(IOError::NoFS);
+            };
+
+            fs.rename(&mut disk.stream(), from, to)
+        })
+    }
 }
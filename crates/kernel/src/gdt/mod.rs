@@ -1,5 +1,6 @@
 use core::arch::asm;
 
+use crate::memory::TSS_STACK;
 use crate::packed::{packed, Packed};
 
 mod tss;
@@ -16,7 +17,7 @@ struct GdtEntry {
 
 const GDT_SIZE: usize = core::mem::size_of::<GdtEntry>();
 
-static TSS: tss::Tss = tss::Tss::new(0x600000, 0x10);
+static TSS: tss::Tss = tss::Tss::new(TSS_STACK as u32, 0x10);
 
 const GDT_SEGMENTS: usize = 6;
 global::global! {Gdts, [GdtEntry; GDT_SEGMENTS], [
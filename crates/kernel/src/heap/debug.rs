@@ -0,0 +1,158 @@
+use core::{mem::size_of, ptr};
+
+use super::{Addr, CallSite, Heap, MemoryError};
+use crate::__trace;
+
+const MAGIC_LIVE: u32 = 0xA110CA7E;
+const MAGIC_FREED: u32 = 0xDEADF7EE;
+
+/// Written over freed memory so that reading it afterwards stands out
+const POISON_BYTE: u8 = 0xDD;
+/// Follow every allocation, anything else in them was written past the end
+const GUARD_BYTE: u8 = 0xFD;
+const GUARD_SIZE: usize = 16;
+
+/// Sits right before the memory handed out, the allocation starts @offset before that.
+/// The magic is last, a freed slab object has its first word overwritten.
+#[repr(C)]
+pub(super) struct Header {
+    size: usize,
+    offset: usize,
+    site: CallSite,
+    prev: *mut Header,
+    next: *mut Header,
+    magic: u32,
+}
+
+impl Header {
+    fn of(ptr: Addr) -> *mut Header {
+        (ptr as usize - size_of::<Header>()) as *mut Header
+    }
+}
+
+/// The guard bytes after the @size bytes at @ptr
+fn guard<'a>(ptr: Addr, size: usize) -> &'a mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(ptr.add(size), GUARD_SIZE) }
+}
+
+impl Heap {
+    pub(super) fn alloc_at(
+        &mut self,
+        size: usize,
+        align: usize,
+        site: CallSite,
+    ) -> Result<Addr, MemoryError> {
+        let offset = size_of::<Header>().next_multiple_of(align);
+        let start = self.alloc(offset + size + GUARD_SIZE, align)?;
+        let ptr = unsafe { start.add(offset) };
+
+        let header = Header::of(ptr);
+        unsafe {
+            header.write(Header {
+                size,
+                offset,
+                site,
+                prev: ptr::null_mut(),
+                next: self.live,
+                magic: MAGIC_LIVE,
+            });
+            if !self.live.is_null() {
+                (*self.live).prev = header;
+            }
+        }
+        guard(ptr, size).fill(GUARD_BYTE);
+        self.live = header;
+
+        Ok(ptr)
+    }
+
+    pub(super) fn realloc_at(
+        &mut self,
+        old: Addr,
+        size: usize,
+        align: usize,
+        site: CallSite,
+    ) -> Result<Addr, MemoryError> {
+        let have = unsafe { (*self.check(old, site)).size };
+
+        let new = self.alloc_at(size, align, site)?;
+        unsafe { ptr::copy_nonoverlapping(old, new, have.min(size)) };
+        self.free_at(old, site);
+
+        Ok(new)
+    }
+
+    pub(super) fn free_at(&mut self, ptr: Addr, site: CallSite) {
+        let header = self.check(ptr, site);
+        let header = unsafe { &mut *header };
+
+        if header.prev.is_null() {
+            self.live = header.next;
+        } else {
+            unsafe { (*header.prev).next = header.next };
+        }
+        if !header.next.is_null() {
+            unsafe { (*header.next).prev = header.prev };
+        }
+
+        header.magic = MAGIC_FREED;
+        unsafe { ptr::write_bytes(ptr, POISON_BYTE, header.size + GUARD_SIZE) };
+
+        self.free(unsafe { ptr.sub(header.offset) });
+    }
+
+    /// Trace every allocation that hasn't been freed yet, with where it was made
+    pub(super) fn dump_allocations(&self) {
+        let mut count = 0;
+        let mut header = self.live;
+        while let Some(live) = unsafe { header.as_ref() } {
+            let ptr = (header as usize + size_of::<Header>()) as Addr;
+            __trace!(
+                "  {:?}: {} bytes from {}:{}\n",
+                ptr,
+                live.size,
+                live.site.file,
+                live.site.line
+            );
+
+            count += 1;
+            header = live.next;
+        }
+
+        __trace!("{} outstanding allocations\n", count);
+    }
+
+    /// The header of @ptr, if it is a live allocation that wasn't written out of bounds
+    fn check(&self, ptr: Addr, site: CallSite) -> *mut Header {
+        assert!(
+            self.contains(ptr),
+            "{}:{}: {ptr:#?} is not in the heap",
+            site.file,
+            site.line
+        );
+
+        let header = unsafe { &mut *Header::of(ptr) };
+        match header.magic {
+            MAGIC_LIVE => {}
+            MAGIC_FREED => panic!("{}:{}: Double free of {ptr:#?}", site.file, site.line),
+            _ => panic!(
+                "{}:{}: {ptr:#?} is not an allocation or its header was overwritten",
+                site.file, site.line
+            ),
+        }
+
+        assert!(
+            guard(ptr, header.size)
+                .iter()
+                .all(|&byte| byte == GUARD_BYTE),
+            "{}:{}: Written past the end of {ptr:#?}, {} bytes from {}:{}",
+            site.file,
+            site.line,
+            header.size,
+            header.site.file,
+            header.site.line
+        );
+
+        header
+    }
+}
@@ -1,43 +1,109 @@
-use super::{Addr, Heap};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{Addr, CallSite, Heap, HeapStats};
+use crate::__trace;
 use crate::global::global;
+use crate::memory::{self, phys_to_virt, KERNEL_HEAP_ENTRIES};
 
-const KERNEL_HEAP_SIZE: usize = 100 * 1024 * 1024; // 100MB
-const KERNEL_HEAP_START: usize = 0x01000000;
-const KERNEL_ENTRIES_START: usize = 0x00007E00;
+// Whether there is a heap to report on when panicking
+static HEAP_READY: AtomicBool = AtomicBool::new(false);
 
 global! {
     KernelHeap,
     Heap,
-    Heap::new(KERNEL_ENTRIES_START, KERNEL_HEAP_SIZE, KERNEL_HEAP_START),
+    {
+        let heap = memory::Layout::heap();
+        let heap = Heap::new(
+            phys_to_virt(KERNEL_HEAP_ENTRIES),
+            heap.len(),
+            phys_to_virt(heap.start),
+        );
+        HEAP_READY.store(true, Ordering::Relaxed);
+        heap
+    },
     "KERNEL_HEAP"
 }
 
-pub fn alloc_<T>(size: usize) -> *mut T {
-    let heap = KernelHeap::get_mut();
+impl KernelHeap {
+    pub fn stats() -> HeapStats {
+        Self::get().with_rlock(|heap| heap.stats())
+    }
+
+    /// Trace the stats without taking the lock, the panic may have happened while holding it
+    pub fn trace_stats_on_panic() {
+        if !HEAP_READY.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let stats = unsafe { Self::get_mut().force() }.stats();
+        __trace!(
+            "Heap: {}/{} blocks used, {} at most, {} free in a row, {} allocations\n",
+            stats.used_blocks,
+            stats.total_blocks,
+            stats.high_water,
+            stats.largest_free_run,
+            stats.allocations
+        );
 
-    heap.with_wlock(|heap| heap.alloc_blocks(Heap::align_block(size)).cast())
+        #[cfg(feature = "heap-debug")]
+        unsafe { Self::get_mut().force() }.dump_allocations();
+    }
+
+    /// Trace every allocation that wasn't freed yet, and where it was made
+    #[cfg(feature = "heap-debug")]
+    pub fn dump_allocations() {
+        Self::get().with_rlock(|heap| heap.dump_allocations())
+    }
 }
 
-pub fn realloc_(old: Addr, size: usize) -> Addr {
+/// Lets `alloc::vec::Vec`, `alloc::boxed::Box` and friends use the kernel heap
+struct KernelAllocator;
+
+#[global_allocator]
+static ALLOCATOR: KernelAllocator = KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        KernelHeap::get_mut()
+            .with_wlock(|heap| heap.alloc_at(layout.size(), layout.align(), CallSite::UNKNOWN))
+            .unwrap_or_default()
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        KernelHeap::set(|heap| heap.free_at(ptr, CallSite::UNKNOWN))
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        KernelHeap::get_mut()
+            .with_wlock(|heap| heap.realloc_at(ptr, new_size, layout.align(), CallSite::UNKNOWN))
+            .unwrap_or_default()
+    }
+}
+
+pub fn alloc_<T>(size: usize, site: CallSite) -> *mut T {
     let heap = KernelHeap::get_mut();
 
-    heap.with_wlock(|heap| -> Addr {
-        let count = Heap::align_block(size);
-        let new = heap.alloc_blocks(count);
-        let src = heap.addr_to_block(new);
-        let dst = heap.addr_to_block(old);
+    match heap.with_wlock(|heap| heap.alloc_at(size, core::mem::align_of::<T>(), site)) {
+        Ok(ptr) => ptr.cast(),
+        Err(_) => panic!("Kernel heap out of memory allocating {size} bytes"),
+    }
+}
 
-        heap.copy_blocks(src, dst, count);
+/// Resize @old to @size bytes, it only moves if it can't grow in place
+pub fn realloc_(old: Addr, size: usize, site: CallSite) -> Addr {
+    let heap = KernelHeap::get_mut();
 
-        new
-    })
+    match heap.with_wlock(|heap| heap.realloc_at(old, size, 1, site)) {
+        Ok(ptr) => ptr,
+        Err(_) => panic!("Kernel heap out of memory reallocating to {size} bytes"),
+    }
 }
 
-pub fn free_<T: ?Sized>(ptr: *mut T) {
+pub fn free_<T: ?Sized>(ptr: *mut T, site: CallSite) {
     let heap = KernelHeap::get_mut();
 
-    heap.with_wlock(|heap| {
-        let start_block = heap.addr_to_block(ptr.cast());
-        heap.mark_blocks_free(start_block);
-    })
+    heap.with_wlock(|heap| heap.free_at(ptr.cast(), site))
 }
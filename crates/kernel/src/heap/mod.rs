@@ -1,7 +1,12 @@
 use core::ptr::{self, Unique};
 
+#[cfg(feature = "heap-debug")]
+mod debug;
 mod kernel_heap;
-pub use kernel_heap::{alloc_, free_, realloc_};
+mod slab;
+pub use kernel_heap::{alloc_, free_, realloc_, KernelHeap};
+
+use slab::{Slab, NUM_CLASSES};
 
 pub const HEAP_BLOCK_SIZE: usize = 4096;
 
@@ -9,38 +14,185 @@ const BLOCK_FREE: u8 = 0;
 const BLOCK_TAKEN: u8 = 1 << 0;
 const BLOCK_FIRST: u8 = 1 << 1;
 const BLOCK_HAS_NEXT: u8 = 1 << 2;
+const BLOCK_SLAB: u8 = 1 << 3; // Cut up into objects
 
 #[derive(Debug)]
-enum MemoryError {
+pub enum MemoryError {
     OutOfMemory,
 }
 
 pub type Addr = *mut u8;
 
+/// Where an allocation was asked for, only kept with the heap-debug feature
+#[derive(Clone, Copy, Debug)]
+pub struct CallSite {
+    pub file: &'static str,
+    pub line: u32,
+}
+
+impl CallSite {
+    /// Allocations through `alloc::` types don't know theirs
+    pub const UNKNOWN: Self = Self::new("<alloc>", 0);
+
+    pub const fn new(file: &'static str, line: u32) -> Self {
+        Self { file, line }
+    }
+}
+
+/// A snapshot of how the heap is used, in blocks unless said otherwise
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    pub total_blocks: usize,
+    pub used_blocks: usize,
+    pub free_blocks: usize,
+    pub largest_free_run: usize,
+    pub allocations: usize, // Live allocations, objects and block runs alike
+    pub high_water: usize,  // The most blocks that were ever used at once
+}
+
+/// Whole blocks for big allocations, small ones are carved out of blocks by size class
 pub struct Heap {
     entries: Unique<[u8]>,
     count: usize,
     start: Addr,
+    slabs: [*mut Slab; NUM_CLASSES], // Slabs with free objects, by size class
+    used: usize,
+    high_water: usize,
+    allocations: usize,
+    #[cfg(feature = "heap-debug")]
+    live: *mut debug::Header, // Every outstanding allocation, newest first
 }
 
 impl Heap {
+    /// The table at @entries is cleared, whatever the loader left there would look taken.
+    /// The blocks start at the first block boundary from @start.
     pub fn new(entries: usize, size: usize, start: usize) -> Self {
-        let real_start = start + start % HEAP_BLOCK_SIZE;
+        let real_start = start.next_multiple_of(HEAP_BLOCK_SIZE);
+        let count = size.saturating_sub(real_start - start) / HEAP_BLOCK_SIZE;
+        // free and size_of tell objects from blocks by whether they are on a boundary
+        assert!(
+            real_start & (HEAP_BLOCK_SIZE - 1) == 0,
+            "Heap blocks start off a block boundary: {real_start:#x}"
+        );
 
         let entries = unsafe {
-            Unique::new_unchecked(ptr::slice_from_raw_parts_mut(
-                entries as *mut u8,
-                size / HEAP_BLOCK_SIZE,
-            ))
+            ptr::write_bytes(entries as *mut u8, BLOCK_FREE, count);
+            Unique::new_unchecked(ptr::slice_from_raw_parts_mut(entries as *mut u8, count))
         };
 
         Self {
             entries,
-            count: size / HEAP_BLOCK_SIZE,
+            count,
             start: real_start as Addr,
+            slabs: [ptr::null_mut(); NUM_CLASSES],
+            used: 0,
+            high_water: 0,
+            allocations: 0,
+            #[cfg(feature = "heap-debug")]
+            live: ptr::null_mut(),
+        }
+    }
+
+    /// Allocate @size bytes aligned to @align, which must be a power of two
+    pub fn alloc(&mut self, size: usize, align: usize) -> Result<Addr, MemoryError> {
+        let ptr = match slab::class(size, align) {
+            Some(class) => self.alloc_object(class),
+            None => self.alloc_blocks(Self::align_block(size), align),
+        }?;
+        self.allocations += 1;
+
+        Ok(ptr)
+    }
+
+    pub fn free(&mut self, ptr: Addr) {
+        assert!(self.contains(ptr), "Freeing outside of the heap: {ptr:#?}");
+
+        // Objects never start a block, the slab header is there
+        if ptr as usize & (HEAP_BLOCK_SIZE - 1) == 0 {
+            let block = self.addr_to_block(ptr);
+            let entry = Self::entry_type(self.entries, block);
+            assert!(
+                entry & (BLOCK_TAKEN | BLOCK_FIRST | BLOCK_SLAB) == BLOCK_TAKEN | BLOCK_FIRST,
+                "Invalid free: {ptr:#?}"
+            );
+            self.mark_blocks_free(block);
+        } else {
+            self.free_object(ptr);
+        }
+        self.allocations -= 1;
+    }
+
+    /// Resize @old to @size bytes, in place if it has room or the blocks after it are free.
+    /// Otherwise it moves to an allocation aligned to @align, keeping as much as fits.
+    fn realloc(&mut self, old: Addr, size: usize, align: usize) -> Result<Addr, MemoryError> {
+        let have = self.size_of(old);
+        if old as usize & (HEAP_BLOCK_SIZE - 1) == 0 {
+            let start = self.addr_to_block(old);
+            let have = have / HEAP_BLOCK_SIZE;
+            let want = Self::align_block(size);
+
+            let end = start + want;
+            if want <= have
+                || (end <= self.count
+                    && (start + have..end).all(|i| Self::entry_type(self.entries, i) == BLOCK_FREE))
+            {
+                self.mark_blocks_free(start);
+                self.mark_blocks_taken(start, want);
+                return Ok(old);
+            }
+        } else if size <= have {
+            return Ok(old);
+        }
+
+        let new = self.alloc(size, align)?;
+        unsafe { ptr::copy_nonoverlapping(old, new, have.min(size)) };
+        self.free(old);
+
+        Ok(new)
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let mut largest_free_run = 0;
+        let mut run = 0;
+        for i in 0..self.count {
+            if Self::entry_type(self.entries, i) == BLOCK_FREE {
+                run += 1;
+                largest_free_run = largest_free_run.max(run);
+            } else {
+                run = 0;
+            }
+        }
+
+        HeapStats {
+            total_blocks: self.count,
+            used_blocks: self.used,
+            free_blocks: self.count - self.used,
+            largest_free_run,
+            allocations: self.allocations,
+            high_water: self.high_water,
         }
     }
 
+    /// How many bytes @ptr has room for
+    pub fn size_of(&self, ptr: Addr) -> usize {
+        if ptr as usize & (HEAP_BLOCK_SIZE - 1) != 0 {
+            return self.object_size(ptr);
+        }
+
+        let mut block = self.addr_to_block(ptr);
+        let mut count = 1;
+        while Self::entry_type(self.entries, block) & BLOCK_HAS_NEXT != 0 {
+            block += 1;
+            count += 1;
+        }
+
+        count * HEAP_BLOCK_SIZE
+    }
+
+    fn contains(&self, ptr: Addr) -> bool {
+        ptr >= self.start && ptr < self.block_to_addr(self.count)
+    }
+
     fn block_to_addr(&self, block: usize) -> Addr {
         (self.start as usize + block * HEAP_BLOCK_SIZE) as Addr
     }
@@ -50,6 +202,9 @@ impl Heap {
     }
 
     fn mark_blocks_taken(&mut self, start_block: usize, total_blocks: usize) {
+        self.used += total_blocks;
+        self.high_water = self.high_water.max(self.used);
+
         if total_blocks == 1 {
             unsafe {
                 self.entries.as_mut()[start_block] = BLOCK_TAKEN | BLOCK_FIRST;
@@ -64,7 +219,7 @@ impl Heap {
                 BLOCK_TAKEN | BLOCK_HAS_NEXT,
                 total_blocks - 2,
             );
-            self.entries.as_mut()[start_block + total_blocks - 1] = BLOCK_TAKEN | BLOCK_FIRST;
+            self.entries.as_mut()[start_block + total_blocks - 1] = BLOCK_TAKEN;
         }
     }
 
@@ -75,6 +230,7 @@ impl Heap {
                 self.entries.as_mut()[i] = BLOCK_FREE;
                 _entry
             };
+            self.used -= 1;
 
             if entry & BLOCK_HAS_NEXT == 0 {
                 break;
@@ -86,55 +242,61 @@ impl Heap {
         unsafe { entries.as_ref()[offset] & 0x0f }
     }
 
-    fn get_free_block(&self, count: usize) -> Result<usize, MemoryError> {
+    /// The first run of @count free blocks starting at an address aligned to @align
+    fn get_free_block(&self, count: usize, align: usize) -> Result<usize, MemoryError> {
         let mut bc = 0;
-        let mut bs: isize = -1;
+        let mut bs = 0;
 
         for i in 0..self.count {
             if Self::entry_type(self.entries, i) != BLOCK_FREE {
                 bc = 0;
-                bs = -1;
                 continue;
             }
 
-            if bs == -1 {
-                bs = i as isize;
+            if bc == 0 {
+                if self.block_to_addr(i) as usize & (align - 1) != 0 {
+                    continue;
+                }
+                bs = i;
             }
 
             bc += 1;
             if bc == count {
-                break;
+                return Ok(bs);
             }
         }
-        if bs == -1 {
-            return Err(MemoryError::OutOfMemory);
-        }
 
-        Ok(bs as usize)
+        Err(MemoryError::OutOfMemory)
     }
 
-    fn alloc_blocks(&mut self, block_count: usize) -> *mut u8 {
-        let start_block = self.get_free_block(block_count).unwrap();
+    fn alloc_blocks(&mut self, block_count: usize, align: usize) -> Result<Addr, MemoryError> {
+        let start_block = self.get_free_block(block_count, align)?;
 
         let addr = self.block_to_addr(start_block);
         self.mark_blocks_taken(start_block, block_count);
 
-        addr
+        Ok(addr)
     }
 
-    fn copy_block(&mut self, src: usize, dst: usize) {
-        let src = self.block_to_addr(src);
-        let dst = self.block_to_addr(dst);
+    #[cfg(not(feature = "heap-debug"))]
+    fn alloc_at(&mut self, size: usize, align: usize, _: CallSite) -> Result<Addr, MemoryError> {
+        self.alloc(size, align)
+    }
 
-        for i in 0..HEAP_BLOCK_SIZE as isize {
-            unsafe { *dst.offset(i) = *src.offset(i) }
-        }
+    #[cfg(not(feature = "heap-debug"))]
+    fn realloc_at(
+        &mut self,
+        old: Addr,
+        size: usize,
+        align: usize,
+        _: CallSite,
+    ) -> Result<Addr, MemoryError> {
+        self.realloc(old, size, align)
     }
 
-    fn copy_blocks(&mut self, src: usize, dst: usize, count: usize) {
-        for i in 0..count {
-            self.copy_block(src + i, dst + i)
-        }
+    #[cfg(not(feature = "heap-debug"))]
+    fn free_at(&mut self, ptr: Addr, _: CallSite) {
+        self.free(ptr)
     }
 
     fn align_block(val: usize) -> usize {
@@ -150,7 +312,7 @@ impl Heap {
 
 macro_rules! alloc {
     ($t:expr) => {{
-        let ptr = crate::heap::alloc_($t);
+        let ptr = crate::heap::alloc_($t, crate::heap::CallSite::new(file!(), line!()));
         crate::traceln!("Allocating: {:?}", ptr);
         ptr
     }};
@@ -158,7 +320,7 @@ macro_rules! alloc {
 
 macro_rules! realloc {
     ($t:expr, $s:expr) => {{
-        let ptr = crate::heap::realloc_($t, $s);
+        let ptr = crate::heap::realloc_($t, $s, crate::heap::CallSite::new(file!(), line!()));
         crate::traceln!("Reallocating: {:?} to {:?}", $t, ptr);
         ptr
     }};
@@ -166,7 +328,7 @@ macro_rules! realloc {
 
 macro_rules! free {
     ($t:expr) => {{
-        crate::heap::free_($t);
+        crate::heap::free_($t, crate::heap::CallSite::new(file!(), line!()));
         crate::traceln!("Freeing: {:?}", $t);
     }};
 }
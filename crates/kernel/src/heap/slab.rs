@@ -0,0 +1,140 @@
+use core::ptr;
+
+use super::{Addr, Heap, MemoryError, BLOCK_SLAB, HEAP_BLOCK_SIZE};
+
+/// Anything bigger gets whole blocks
+pub const MAX_OBJECT_SIZE: usize = 1024;
+const MIN_OBJECT_SIZE: usize = 16;
+pub const NUM_CLASSES: usize = 7; // 16, 32, ..., 1024
+
+/// A block cut up into objects of one size class.
+/// This header takes the place of the first object.
+pub struct Slab {
+    size: usize,
+    used: usize,
+    free: *mut FreeObject,
+    next: *mut Slab, // The next slab of the class that has free objects
+}
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// The size class that fits @size bytes aligned to @align, None if it is too big for a slab.
+/// Objects are aligned to their size, which is a power of two.
+pub(super) fn class(size: usize, align: usize) -> Option<usize> {
+    let size = size
+        .max(align)
+        .max(MIN_OBJECT_SIZE)
+        .checked_next_power_of_two()?;
+
+    (size <= MAX_OBJECT_SIZE)
+        .then(|| (size.trailing_zeros() - MIN_OBJECT_SIZE.trailing_zeros()) as usize)
+}
+
+fn class_size(class: usize) -> usize {
+    MIN_OBJECT_SIZE << class
+}
+
+/// The slab @object lives in
+fn slab_of(object: Addr) -> *mut Slab {
+    (object as usize & !(HEAP_BLOCK_SIZE - 1)) as *mut Slab
+}
+
+impl Heap {
+    pub(super) fn alloc_object(&mut self, class: usize) -> Result<Addr, MemoryError> {
+        if self.slabs[class].is_null() {
+            self.new_slab(class)?;
+        }
+
+        let slab = unsafe { &mut *self.slabs[class] };
+        let object = slab.free;
+        slab.free = unsafe { (*object).next };
+        slab.used += 1;
+
+        // Full, it goes back on the list once something is freed
+        if slab.free.is_null() {
+            self.slabs[class] = slab.next;
+            slab.next = ptr::null_mut();
+        }
+
+        Ok(object.cast())
+    }
+
+    pub(super) fn free_object(&mut self, object: Addr) {
+        let slab_ptr = slab_of(object);
+        let block = self.addr_to_block(slab_ptr.cast());
+        assert!(
+            Self::entry_type(self.entries, block) & BLOCK_SLAB != 0,
+            "Invalid free: {object:#?}"
+        );
+
+        let slab = unsafe { &mut *slab_ptr };
+        assert!(
+            slab.used > 0 && (object as usize - slab_ptr as usize) & (slab.size - 1) == 0,
+            "Invalid free: {object:#?}"
+        );
+
+        let class = class(slab.size, 1).unwrap();
+        let was_full = slab.free.is_null();
+
+        let object: *mut FreeObject = object.cast();
+        unsafe { (*object).next = slab.free };
+        slab.free = object;
+        slab.used -= 1;
+
+        if slab.used == 0 {
+            if !was_full {
+                self.unlink_slab(class, slab_ptr);
+            }
+            self.mark_blocks_free(block);
+        } else if was_full {
+            slab.next = self.slabs[class];
+            self.slabs[class] = slab_ptr;
+        }
+    }
+
+    /// How many bytes @object has room for
+    pub(super) fn object_size(&self, object: Addr) -> usize {
+        unsafe { (*slab_of(object)).size }
+    }
+
+    fn new_slab(&mut self, class: usize) -> Result<(), MemoryError> {
+        let block = self.alloc_blocks(1, HEAP_BLOCK_SIZE)?;
+        let index = self.addr_to_block(block);
+        unsafe { self.entries.as_mut()[index] |= BLOCK_SLAB };
+        let size = class_size(class);
+
+        // Thread every object but the first onto the free list, in order
+        let mut free = ptr::null_mut();
+        for offset in (size..HEAP_BLOCK_SIZE).step_by(size).rev() {
+            let object = (block as usize + offset) as *mut FreeObject;
+            unsafe { (*object).next = free };
+            free = object;
+        }
+
+        let slab: *mut Slab = block.cast();
+        unsafe {
+            slab.write(Slab {
+                size,
+                used: 0,
+                free,
+                next: self.slabs[class],
+            })
+        };
+        self.slabs[class] = slab;
+
+        Ok(())
+    }
+
+    fn unlink_slab(&mut self, class: usize, slab: *mut Slab) {
+        let mut link = &mut self.slabs[class];
+        while !link.is_null() {
+            if *link == slab {
+                *link = unsafe { (*slab).next };
+                return;
+            }
+            link = unsafe { &mut (**link).next };
+        }
+    }
+}
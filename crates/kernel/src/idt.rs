@@ -2,8 +2,9 @@ use core::arch::asm;
 
 use crate::{
     cpu::InterruptFrame,
-    io::outb,
+    exception::{self, NUM_EXCEPTIONS},
     packed::{packed, Packed},
+    pic::PIC,
     traceln,
 };
 
@@ -24,24 +25,19 @@ const MAX_INTERRUPTS: usize = 255;
 // See docs
 interrupts::interrupt_table!(255);
 
+/// Everything that doesn't have a registered handler ends up here
 #[no_mangle]
-fn interrupt_handler(i: u16, frame: *const InterruptFrame, code: u16) {
-    unsafe {
-        traceln!("Interrupted: {} ({:b}): {}", i, code, *frame);
-    };
-
-    if i == 13 {
-        let addr = unsafe {
-            let p: usize;
-            asm!(
-            "mov eax, cr2"
-            , out("eax") p);
-            p
-        };
-        panic!("UNHANDLED PAGE FAULT AT {}", addr);
+extern "C" fn interrupt_handler(i: u32, frame: *const InterruptFrame) {
+    let frame = unsafe { &*frame };
+
+    if i < NUM_EXCEPTIONS {
+        exception::handle(i as u8, frame);
+        return;
     }
 
-    outb(0x20, 0x20);
+    if PIC::acknowledge(i as u8) {
+        traceln!("Unhandled interrupt: {}", i);
+    }
 }
 
 #[packed]
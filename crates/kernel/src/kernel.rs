@@ -2,30 +2,59 @@
 #![no_main]
 
 use kernel::{
+    boot::Boot,
+    cmdline::{ConsoleDevice, Options},
     fs::VFS,
     gdt::GDT,
     idt::IDT,
-    paging::{KernelPage, Paging},
+    keyboard::Keyboard,
+    memory::Layout,
+    paging::KernelPage,
+    pic::PIC,
+    pit::{PIT, TIMER_HZ},
     println,
     process::Process,
-    tty::Terminal,
+    serial,
+    tty::{Terminal, Tty},
 };
 
 #[no_mangle]
-extern "C" fn kmain() {
+extern "C" fn kmain(magic: u32, info: usize) {
+    // Drop the mapping the boot code needed to get here
+    KernelPage::switch();
+
+    serial::init();
     Terminal::init();
     println!("Booting ruix v0.0.1");
 
+    let boot = Boot::init(magic, info);
+    if !boot.cmdline().is_empty() {
+        println!("Command line: {}", boot.cmdline());
+    }
+
+    Layout::init(boot.memory_map());
+
     GDT::load();
 
     IDT::load();
+    PIC::init();
+    serial::enable_input();
 
     VFS::resolve().expect("Resolve disks");
 
-    KernelPage::switch();
-    Paging::enable();
+    // The boot loader's command line wins over BOOT.CFG
+    let options = Options::init(boot.cmdline());
+    if options.console() == ConsoleDevice::Serial {
+        Tty::mirror_to_serial(true);
+    }
+
+    Process::idle().expect("Create idle process");
+    let init = options.init_program();
+    let process =
+        Process::new(init, None).unwrap_or_else(|err| panic!("Could not start {init}: {err:?}"));
 
-    let process = Process::new("0:/SHELL").unwrap();
+    Keyboard::init();
+    PIT::init(TIMER_HZ);
     Process::exec(process);
 
     unsafe { core::arch::asm!("hlt") };
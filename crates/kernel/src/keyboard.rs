@@ -0,0 +1,169 @@
+use global::global;
+use interrupts::isr;
+
+use crate::{
+    boxed::RingBuffer,
+    cpu::InterruptFrame,
+    io::insb,
+    pic::{IRQ_KEYBOARD, PIC},
+    tty::Tty,
+};
+
+const KEYBOARD_DATA: u16 = 0x60;
+const KEYBOARD_STATUS: u16 = 0x64;
+const KEYBOARD_OUTPUT_FULL: u8 = 1 << 0;
+
+const KEY_BUFFER_SIZE: usize = 64;
+
+// Set 1 scancodes
+const RELEASED: u8 = 0x80;
+const EXTENDED: u8 = 0xE0;
+const LEFT_CTRL: u8 = 0x1D;
+const LEFT_SHIFT: u8 = 0x2A;
+const RIGHT_SHIFT: u8 = 0x36;
+const CAPS_LOCK: u8 = 0x3A;
+const KEYPAD_START: u8 = 0x47;
+
+// Indexed by make code, 0 for keys that don't produce anything on their own
+const NORMAL: &[u8; 0x3A] =
+    b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+const SHIFTED: &[u8; 0x3A] =
+    b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+// 0x47..=0x53, num lock is assumed to be on
+const KEYPAD: &[u8; 13] = b"789-456+1230.";
+
+/// Modifier state of the keyboard
+pub struct Keyboard {
+    shift: bool,
+    ctrl: bool,
+    caps_lock: bool,
+    extended: bool, // The previous scancode was the 0xE0 prefix
+}
+
+global!(KeyboardState, Keyboard, Keyboard::new(), "KEYBOARD");
+global!(
+    Keys,
+    RingBuffer<u8, KEY_BUFFER_SIZE>,
+    RingBuffer::new(),
+    "KEYBOARD_BUFFER"
+);
+
+impl Keyboard {
+    const fn new() -> Self {
+        Self {
+            shift: false,
+            ctrl: false,
+            caps_lock: false,
+            extended: false,
+        }
+    }
+
+    /// Throw away whatever the controller has buffered since boot
+    pub fn init() {
+        while insb(KEYBOARD_STATUS) & KEYBOARD_OUTPUT_FULL != 0 {
+            insb(KEYBOARD_DATA);
+        }
+
+        PIC::unmask(IRQ_KEYBOARD);
+    }
+
+    /// Take the next translated byte
+    pub fn pop() -> Option<u8> {
+        Keys::get_mut().with_wlock(|keys| keys.pop())
+    }
+
+    /// Update the modifiers with @scancode and return the bytes it translates to
+    fn translate(&mut self, scancode: u8) -> &'static [u8] {
+        if scancode == EXTENDED {
+            self.extended = true;
+            return &[];
+        }
+
+        let extended = core::mem::replace(&mut self.extended, false);
+        let released = scancode & RELEASED != 0;
+        let code = scancode & !RELEASED;
+
+        match code {
+            LEFT_SHIFT | RIGHT_SHIFT if !extended => self.shift = !released,
+            LEFT_CTRL => self.ctrl = !released, // Extended is the right one
+            CAPS_LOCK if !released => self.caps_lock = !self.caps_lock,
+            _ => {}
+        }
+
+        if released {
+            return &[];
+        }
+
+        if extended {
+            return Self::extended(code);
+        }
+
+        match code {
+            KEYPAD_START..=0x53 => {
+                let i = (code - KEYPAD_START) as usize;
+                &KEYPAD[i..i + 1]
+            }
+            0..0x3A => {
+                let i = code as usize;
+                let table = if self.shift { SHIFTED } else { NORMAL };
+                let byte = table[i];
+
+                if byte == 0 {
+                    &[]
+                } else if self.ctrl && byte.is_ascii_alphabetic() {
+                    Self::control(byte)
+                } else if self.caps_lock && byte.is_ascii_alphabetic() {
+                    // Caps lock inverts shift, but only for letters
+                    let table = if self.shift { NORMAL } else { SHIFTED };
+                    &table[i..i + 1]
+                } else {
+                    &table[i..i + 1]
+                }
+            }
+            _ => &[],
+        }
+    }
+
+    /// Ctrl+letter is the letter's position in the alphabet, Ctrl-A is 1
+    fn control(letter: u8) -> &'static [u8] {
+        const CONTROL: &[u8; 26] = b"\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\x10\x11\x12\x13\x14\x15\x16\x17\x18\x19\x1a";
+
+        let i = (letter.to_ascii_lowercase() - b'a') as usize;
+        &CONTROL[i..i + 1]
+    }
+
+    /// Keys behind the 0xE0 prefix, the navigation keys become ANSI escape sequences
+    fn extended(code: u8) -> &'static [u8] {
+        match code {
+            0x1C => b"\n", // Keypad enter
+            0x35 => b"/",  // Keypad slash
+            0x47 => b"\x1b[H",
+            0x48 => b"\x1b[A",
+            0x49 => b"\x1b[5~",
+            0x4B => b"\x1b[D",
+            0x4D => b"\x1b[C",
+            0x4F => b"\x1b[F",
+            0x50 => b"\x1b[B",
+            0x51 => b"\x1b[6~",
+            0x52 => b"\x1b[2~",
+            0x53 => b"\x1b[3~",
+            _ => &[],
+        }
+    }
+}
+
+#[isr(0x21)]
+fn keyboard_input(_frame: *const InterruptFrame) {
+    let scancode = insb(KEYBOARD_DATA);
+
+    let bytes = KeyboardState::get_mut().with_wlock(|keyboard| keyboard.translate(scancode));
+    Keys::set(|keys| {
+        for byte in bytes {
+            keys.push(*byte);
+        }
+    });
+
+    while let Some(byte) = Keyboard::pop() {
+        Tty::input(byte);
+    }
+}
@@ -7,29 +7,38 @@
 #![feature(unsize)]
 #![allow(dead_code)]
 #![allow(bad_asm_style)]
+extern crate alloc;
 extern crate global;
 extern crate packed;
 pub extern crate syscalls;
 
 #[macro_use]
 pub mod heap;
-mod boot;
+pub mod boot;
 pub mod boxed;
+pub mod cmdline;
 pub mod cpu;
 pub mod disk;
+pub mod exception;
 pub mod fs;
 pub mod gdt;
 pub mod idt;
 pub mod io;
+pub mod keyboard;
 pub mod loader;
+pub mod memory;
 pub mod paging;
 pub mod path;
+pub mod pic;
+pub mod pit;
 pub mod process;
+pub mod rtc;
 pub mod start;
 pub mod string;
 pub mod syscall;
 pub mod task;
 pub mod tty;
+pub mod uaccess;
 #[macro_use]
 pub mod serial;
 pub mod sync;
@@ -29,8 +29,9 @@ pub struct Elf {
 
 impl Elf {
     pub fn load(filename: &str) -> Result<Self, super::Error> {
-        let fd = VFS::open(Path::new(filename), FileMode::ReadOnly).unwrap();
-        let mut file = fd.read_all().unwrap();
+        let fd = VFS::open(Path::new(filename), FileMode::READ_ONLY)
+            .map_err(|_| super::Error::NotFound)?;
+        let mut file = fd.read_all().map_err(|_| super::Error::NotFound)?;
         let header = Header::from_bytes(&file[..Header::size()]);
 
         let ptr = file.as_ptr() as usize;
@@ -117,6 +118,12 @@ impl Elf {
         arr
     }
 
+    /// What @pheader loads from the file, None if it points outside of it
+    pub fn data(&self, pheader: &PHeader) -> Option<&[u8]> {
+        let end = pheader.offset().checked_add(pheader.filesz())?;
+        self.file.get(pheader.offset()..end)
+    }
+
     pub fn free(&mut self) {
         self.file.free()
     }
@@ -19,6 +19,8 @@ pub(super) const ELF_CLASS_32: u8 = 1;
 pub(super) const ELF_DATA_NONE: u8 = 0;
 pub(super) const ELF_DATA_2LSB: u8 = 1;
 
+pub(super) const PT_LOAD: usize = 1;
+
 pub(super) const PF_X: usize = 0x01;
 pub(super) const PF_W: usize = 0x02;
 pub(super) const PF_R: usize = 0x04;
@@ -36,6 +38,9 @@ pub struct PHeader {
 }
 
 impl PHeader {
+    pub fn is_loadable(&self) -> bool {
+        self.p_type == PT_LOAD
+    }
     pub fn is_exec(&self) -> bool {
         self.p_flags & PF_X != 0
     }
@@ -49,6 +54,9 @@ impl PHeader {
     pub fn memsz(&self) -> Word {
         self.p_memsz
     }
+    pub fn offset(&self) -> Offset {
+        self.p_offset
+    }
     pub fn vaddr(&self) -> usize {
         self.p_vaddr
     }
@@ -3,4 +3,5 @@ pub mod elf;
 #[derive(Debug)]
 pub enum Error {
     BadFormat,
+    NotFound,
 }
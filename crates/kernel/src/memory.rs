@@ -0,0 +1,249 @@
+use core::ops::Range;
+
+use global::global;
+
+use crate::{heap::HEAP_BLOCK_SIZE, paging::PAGE_SIZE, println};
+
+/// The kernel is linked here and the physical memory it uses is mapped from here on,
+/// in every address space. Must match linker.ld and boot/multiboot.S.
+pub const KERNEL_BASE: usize = 0xC0000000;
+/// How much physical memory fits above KERNEL_BASE
+pub const KERNEL_SPACE_SIZE: usize = 0x40000000;
+
+/// Where the boot sector leaves the BIOS E820 map, must match boot/x86.S
+pub const MEMORY_MAP: usize = 0x500;
+pub const MAX_MEMORY_REGIONS: usize = 32;
+
+/// The kernel image, its stacks and the TSS stack live below this
+pub const KERNEL_HEAP_START: usize = 0x01000000;
+/// Bookkeeping of the heap, one byte per block.
+/// Loaders may leave their info here, it is cleared once BootInfo has copied what it needs.
+pub const KERNEL_HEAP_ENTRIES: usize = 0x00007E00;
+/// Ring 0 stack the CPU switches to when user land is interrupted
+pub const TSS_STACK: usize = KERNEL_BASE + 0x600000;
+/// The stack kmain runs on, must match boot/multiboot.S
+pub const KERNEL_STACK: usize = KERNEL_BASE + 0x200000;
+const KERNEL_IMAGE_START: usize = 0x100000;
+const KERNEL_HEAP_ENTRIES_END: usize = KERNEL_HEAP_ENTRIES + MAX_HEAP_SIZE / HEAP_BLOCK_SIZE;
+
+const MIN_HEAP_SIZE: usize = 4 * 1024 * 1024; // 4MB
+const MAX_HEAP_SIZE: usize = 32 * 1024 * 1024; // 32MB
+
+// Page tables only exist where something is mapped, this is plenty for the idle process and a shell
+const MIN_FRAMES_SIZE: usize = 4 * 1024 * 1024; // 4MB
+
+const E820_USABLE: u32 = 1;
+
+/// Whether the physical memory in @range overlaps what the kernel has at fixed places:
+/// the bookkeeping in low memory, the image with both stacks, the heap and the frames.
+/// Which of it is in use at boot isn't known, the stacks grow down to wherever they need.
+pub fn is_reserved(range: Range<usize>) -> bool {
+    let fixed = [
+        MEMORY_MAP..KERNEL_HEAP_ENTRIES_END,
+        KERNEL_IMAGE_START..TSS_STACK - KERNEL_BASE,
+        KERNEL_HEAP_START..usize::MAX,
+    ];
+
+    fixed
+        .iter()
+        .any(|fixed| range.start < fixed.end && fixed.start < range.end)
+}
+
+/// An entry in the E820 map
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct MemoryRegion {
+    base: u64,
+    length: u64,
+    kind: u32,
+    acpi: u32,
+}
+
+/// The memory map as the boot sector left it, or made from the multiboot one
+#[repr(C, packed)]
+pub struct MemoryMap {
+    count: u32,
+    regions: [MemoryRegion; MAX_MEMORY_REGIONS],
+}
+
+impl MemoryRegion {
+    /// The whole pages of the region we can address, if it is usable
+    fn usable(&self) -> Option<Range<usize>> {
+        let limit = (usize::MAX - PAGE_SIZE + 1) as u64;
+        let start = self.base.min(limit) as usize;
+        let end = self.base.saturating_add(self.length).min(limit) as usize;
+
+        let start = start.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        let end = end / PAGE_SIZE * PAGE_SIZE;
+
+        (self.kind == E820_USABLE && start < end).then_some(start..end)
+    }
+}
+
+impl MemoryMap {
+    pub const fn empty() -> Self {
+        Self {
+            count: 0,
+            regions: [MemoryRegion {
+                base: 0,
+                length: 0,
+                kind: 0,
+                acpi: 0,
+            }; MAX_MEMORY_REGIONS],
+        }
+    }
+
+    /// Add a region like the BIOS would, false if the map is full
+    pub fn push(&mut self, base: u64, length: u64, kind: u32) -> bool {
+        let count = self.count as usize;
+        if count == MAX_MEMORY_REGIONS {
+            return false;
+        }
+
+        self.regions[count] = MemoryRegion {
+            base,
+            length,
+            kind,
+            acpi: 0,
+        };
+        self.count += 1;
+
+        true
+    }
+
+    pub fn regions(&self) -> &[MemoryRegion] {
+        let count = (self.count as usize).min(MAX_MEMORY_REGIONS);
+        &self.regions[..count]
+    }
+
+    /// The usable RAM, page aligned. Regions may overlap and are in no particular order.
+    pub fn usable(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.regions().iter().filter_map(MemoryRegion::usable)
+    }
+
+    /// How far usable RAM goes on without a hole from @start
+    fn usable_end(&self, start: usize) -> usize {
+        let mut end = start;
+        while let Some(region) = self.usable().find(|region| region.contains(&end)) {
+            end = region.end;
+        }
+
+        end
+    }
+
+    fn is_usable(&self, range: Range<usize>) -> bool {
+        self.usable_end(range.start) >= range.end
+    }
+}
+
+/// Where the kernel sees physical address @paddr
+pub const fn phys_to_virt(paddr: usize) -> usize {
+    paddr + KERNEL_BASE
+}
+
+/// The physical address behind @vaddr, which must be in the kernel's mapping of physical memory
+pub const fn virt_to_phys(vaddr: usize) -> usize {
+    vaddr - KERNEL_BASE
+}
+
+/// How the physical memory is split between the kernel heap and the frame allocator
+pub struct MemoryLayout {
+    map: Option<&'static MemoryMap>,
+    heap: Range<usize>,
+    frames: Range<usize>,
+}
+
+global!(Layout, MemoryLayout, MemoryLayout::new(), "MEMORY_LAYOUT");
+
+impl Layout {
+    /// Size the heap and the frames from @map, there is no coming back if they don't fit.
+    /// This must come before anything is allocated.
+    pub fn init(map: &'static MemoryMap) {
+        let layout = match MemoryLayout::from_map(map) {
+            Ok(layout) => layout,
+            Err(found) => {
+                println!(
+                    "Not enough memory: {} KiB usable, {} KiB needed",
+                    found / 1024,
+                    (KERNEL_HEAP_START + MIN_HEAP_SIZE + MIN_FRAMES_SIZE) / 1024
+                );
+                panic!("Not enough memory");
+            }
+        };
+
+        println!(
+            "Memory: {} KiB heap, {} KiB frames",
+            layout.heap.len() / 1024,
+            layout.frames.len() / 1024
+        );
+
+        Self::set(|this| *this = layout);
+    }
+
+    pub fn map() -> &'static MemoryMap {
+        Self::get()
+            .with_rlock(|layout| layout.map)
+            .expect("Memory layout is not initialized")
+    }
+
+    pub fn heap() -> Range<usize> {
+        let heap = Self::get().with_rlock(|layout| layout.heap.clone());
+        assert!(!heap.is_empty(), "Memory layout is not initialized");
+        heap
+    }
+
+    pub fn frames() -> Range<usize> {
+        let frames = Self::get().with_rlock(|layout| layout.frames.clone());
+        assert!(!frames.is_empty(), "Memory layout is not initialized");
+        frames
+    }
+}
+
+impl MemoryLayout {
+    const fn new() -> Self {
+        Self {
+            map: None,
+            heap: 0..0,
+            frames: 0..0,
+        }
+    }
+
+    /// The heap gets a quarter of what the frames can spare, the frames the rest.
+    /// Fails with the amount of memory there is, if it isn't enough.
+    fn from_map(map: &'static MemoryMap) -> Result<Self, usize> {
+        let found = map.usable().map(|region| region.len()).sum();
+
+        // The kernel and its bookkeeping have fixed places
+        if !map.is_usable(MEMORY_MAP..KERNEL_HEAP_ENTRIES_END)
+            || !map.is_usable(KERNEL_IMAGE_START..KERNEL_HEAP_START)
+        {
+            return Err(found);
+        }
+
+        // The frames right after the heap hold the allocator's bookkeeping
+        let end = map.usable_end(KERNEL_HEAP_START);
+        let spare = end.saturating_sub(KERNEL_HEAP_START + MIN_FRAMES_SIZE);
+        if spare < MIN_HEAP_SIZE {
+            return Err(found);
+        }
+
+        let heap_size = (spare / 4).clamp(MIN_HEAP_SIZE, MAX_HEAP_SIZE);
+        let heap =
+            KERNEL_HEAP_START..KERNEL_HEAP_START + heap_size / HEAP_BLOCK_SIZE * HEAP_BLOCK_SIZE;
+
+        // Holes past the heap are left to the frame allocator to skip
+        let frames_end = map
+            .usable()
+            .map(|region| region.end)
+            .max()
+            .unwrap_or(end)
+            .min(KERNEL_SPACE_SIZE);
+        let frames = heap.end..frames_end;
+
+        Ok(Self {
+            map: Some(map),
+            heap,
+            frames,
+        })
+    }
+}
@@ -0,0 +1,180 @@
+use core::ops::Range;
+
+use global::global;
+
+use super::{Addr, PAGE_SIZE};
+use crate::memory::{phys_to_virt, Layout, MemoryMap};
+
+const BITS_PER_WORD: usize = usize::BITS as usize;
+
+/// Owns the usable memory past the kernel heap in 4 KiB frames, handed out by physical address.
+/// A bitmap tracks which frames are taken and every taken frame is reference counted,
+/// so a frame can be mapped in many places and is only freed once nobody uses it.
+pub struct FrameAllocator {
+    start: usize, // The first frame that is handed out
+    count: usize,
+    free: usize,
+    next: usize, // Where to start looking for a free frame
+    bitmap: *mut usize,
+    refcounts: *mut u16,
+}
+
+global!(
+    Frames,
+    FrameAllocator,
+    FrameAllocator::new(Layout::frames(), Layout::map()),
+    "FRAMES"
+);
+
+impl Frames {
+    pub fn alloc() -> Option<Addr> {
+        Self::get_mut().with_wlock(|frames| frames.alloc())
+    }
+
+    pub fn alloc_zeroed() -> Option<Addr> {
+        let frame = Self::alloc()?;
+        unsafe { core::ptr::write_bytes(phys_to_virt(frame.0) as *mut u8, 0, PAGE_SIZE) };
+        Some(frame)
+    }
+
+    /// Take another reference to @frame
+    pub fn share(frame: Addr) {
+        Self::set(|frames| frames.share(frame))
+    }
+
+    /// Drop a reference to @frame, it is free once the last one is gone
+    pub fn free(frame: Addr) {
+        Self::set(|frames| frames.release(frame))
+    }
+
+    pub fn refcount(frame: Addr) -> u16 {
+        Self::get().with_rlock(|frames| frames.refcount(frame))
+    }
+
+    pub fn available() -> usize {
+        Self::get().with_rlock(|frames| frames.free)
+    }
+}
+
+impl FrameAllocator {
+    /// Manage the frames of @range that @map says are usable.
+    /// The bookkeeping is kept in the first of them, so those must be.
+    pub fn new(range: Range<usize>, map: &MemoryMap) -> Self {
+        let start = Addr(range.start).align_upper().0;
+        let total = (range.end - start) / PAGE_SIZE;
+
+        let words = total.div_ceil(BITS_PER_WORD);
+        let bookkeeping = words * core::mem::size_of::<usize>() + total * 2;
+        let reserved = Addr(bookkeeping).align_upper().0 / PAGE_SIZE;
+        assert!(reserved < total, "No memory for frames");
+
+        // Everything is taken until we know it is usable
+        let bitmap = phys_to_virt(start) as *mut usize;
+        let refcounts = phys_to_virt(start + words * core::mem::size_of::<usize>()) as *mut u16;
+        unsafe {
+            core::ptr::write_bytes(bitmap, 0xFF, words);
+            core::ptr::write_bytes(refcounts, 0, total);
+        }
+
+        let mut this = Self {
+            start: start + reserved * PAGE_SIZE,
+            count: total - reserved,
+            free: 0,
+            next: 0,
+            bitmap,
+            refcounts,
+        };
+
+        for region in map.usable() {
+            this.add(region);
+        }
+
+        this
+    }
+
+    /// Hand out the frames in @region, the parts outside of what we manage are ignored
+    fn add(&mut self, region: Range<usize>) {
+        let end = self.start + self.count * PAGE_SIZE;
+        let start = region.start.clamp(self.start, end);
+        let region_end = region.end.clamp(self.start, end);
+
+        for index in (start - self.start) / PAGE_SIZE..(region_end - self.start) / PAGE_SIZE {
+            let word = unsafe { &mut *self.bitmap.add(index / BITS_PER_WORD) };
+            let bit = 1 << (index % BITS_PER_WORD);
+
+            // Usable regions may overlap
+            if *word & bit != 0 {
+                *word &= !bit;
+                self.free += 1;
+            }
+        }
+    }
+
+    fn alloc(&mut self) -> Option<Addr> {
+        let words = self.count.div_ceil(BITS_PER_WORD);
+        let first = self.next / BITS_PER_WORD;
+
+        for word in (first..words).chain(0..first) {
+            let bits = unsafe { *self.bitmap.add(word) };
+            if bits == usize::MAX {
+                continue;
+            }
+
+            let index = word * BITS_PER_WORD + (!bits).trailing_zeros() as usize;
+            if index >= self.count {
+                continue;
+            }
+
+            unsafe {
+                *self.bitmap.add(word) = bits | 1 << (index % BITS_PER_WORD);
+                *self.refcounts.add(index) = 1;
+            }
+            self.free -= 1;
+            self.next = index + 1;
+
+            return Some(Addr(self.start + index * PAGE_SIZE));
+        }
+
+        None
+    }
+
+    fn share(&mut self, frame: Addr) {
+        let index = self.index(frame);
+        let refcount = unsafe { &mut *self.refcounts.add(index) };
+        assert!(*refcount > 0, "Sharing a free frame: {frame}");
+
+        *refcount = refcount
+            .checked_add(1)
+            .expect("Too many references to frame");
+    }
+
+    fn release(&mut self, frame: Addr) {
+        let index = self.index(frame);
+        let refcount = unsafe { &mut *self.refcounts.add(index) };
+        assert!(*refcount > 0, "Double free of frame: {frame}");
+
+        *refcount -= 1;
+        if *refcount > 0 {
+            return;
+        }
+
+        unsafe { *self.bitmap.add(index / BITS_PER_WORD) &= !(1 << (index % BITS_PER_WORD)) };
+        self.free += 1;
+        self.next = self.next.min(index);
+    }
+
+    fn refcount(&self, frame: Addr) -> u16 {
+        unsafe { *self.refcounts.add(self.index(frame)) }
+    }
+
+    fn index(&self, frame: Addr) -> usize {
+        assert!(
+            frame.is_aligned()
+                && frame.0 >= self.start
+                && frame.0 < self.start + self.count * PAGE_SIZE,
+            "Not a frame: {frame}"
+        );
+
+        (frame.0 - self.start) / PAGE_SIZE
+    }
+}
@@ -1,15 +1,17 @@
 use core::arch::asm;
 
+pub mod frame;
 pub mod pagedirectory;
 pub mod pagetable;
 
 use global::global;
 use pagedirectory::PageDirectory;
 
+// Only the kernel half, which every other directory shares
 global! {
     KernelPage,
     PageDirectory,
-    PageDirectory::new(PAGE_IS_WRITABLE | PAGE_IS_PRESENT),
+    PageDirectory::kernel(crate::boot::page_directory()),
     "KERNEL_PAGE_DIRECTORY"
 }
 
@@ -18,47 +20,35 @@ impl KernelPage {
         let directory = Self::get();
         directory.with_rlock(Paging::switch)
     }
-
-    pub fn map(vaddr: Addr, paddr: Addr, flags: Flags) {
-        let directory = Self::get_mut();
-        directory.with_wlock(|dir| dir.map(vaddr, paddr, flags))
-    }
 }
 
-global::global!(
-    Current,
-    PageDirectory,
-    PageDirectory::new(0),
-    "CURRENT_DIRECTORY"
-);
-
+/// Paging is enabled by the boot code, before the kernel runs where it is linked
 pub struct Paging;
 impl Paging {
-    pub fn enable() {
-        unsafe {
-            asm!(
-                r#"
-                mov eax, cr0
-                or eax, 0x80000000
-                mov cr0, eax
-                "#
-            )
-        }
-    }
-
     pub fn switch(directory: &PageDirectory) {
         unsafe {
             asm!(
                 r#"
                 mov cr3, eax
-            "#, in("eax") directory.ptr()
+            "#, in("eax") directory.addr().0
             )
         }
-        Current::get_mut().with_wlock(|this| *this = *directory);
+    }
+
+    /// Drop what the TLB remembers about @vaddr
+    pub fn invalidate(vaddr: Addr) {
+        unsafe { asm!("invlpg [{}]", in(reg) vaddr.0) }
+    }
+
+    /// The physical address of the loaded directory
+    pub fn current() -> Addr {
+        let cr3: usize;
+        unsafe { asm!("mov {}, cr3", out(reg) cr3) };
+        Addr(cr3)
     }
 }
 
-const ENTRIES_PER_TABLE: usize = 1024;
+pub const ENTRIES_PER_TABLE: usize = 1024;
 
 #[derive(Clone, Copy)]
 pub struct Addr(pub usize);
@@ -91,7 +81,7 @@ impl Addr {
         Self(self.0 + (PAGE_SIZE - self.0 % PAGE_SIZE))
     }
 
-    fn is_aligned(&self) -> bool {
+    pub fn is_aligned(&self) -> bool {
         self.0 % PAGE_SIZE == 0
     }
 
@@ -117,10 +107,13 @@ impl core::fmt::Display for Addr {
 pub struct Page(pub usize);
 pub struct Offset(pub usize);
 
-type Flags = u16;
+pub type Flags = u16;
 pub const PAGE_IS_PRESENT: Flags = 1 << 0;
 pub const PAGE_IS_WRITABLE: Flags = 1 << 1;
 pub const PAGE_ACCESS_ALL: Flags = 1 << 2;
 pub const PAGE_WRITE_THROUGH: Flags = 1 << 3;
 pub const PAGE_CACHE_DISABLED: Flags = 1 << 4;
+pub const PAGE_WAS_ACCESSED: Flags = 1 << 5; // Set by the CPU
+pub const PAGE_IS_DIRTY: Flags = 1 << 6; // Set by the CPU
+pub const PAGE_IS_LARGE: Flags = 1 << 7; // Maps 4 MiB, only in a directory
 pub const PAGE_SIZE: usize = 4096;
@@ -1,112 +1,154 @@
 use core::marker::PhantomData;
-use core::ops::Range;
 
-use crate::{trace, traceln};
+use crate::memory::{phys_to_virt, virt_to_phys, KERNEL_BASE};
+use crate::traceln;
 
 use super::{
-    pagetable::{PageTable, PageTableEntry, ENTRY_SIZE},
-    Addr, Flags, Offset, Page, ENTRIES_PER_TABLE, PAGE_SIZE,
+    frame::Frames,
+    pagetable::{PageTable, PageTableEntry},
+    Addr, Flags, KernelPage, Page, Paging, ENTRIES_PER_TABLE, PAGE_ACCESS_ALL, PAGE_IS_DIRTY,
+    PAGE_IS_PRESENT, PAGE_IS_WRITABLE, PAGE_SIZE, PAGE_WAS_ACCESSED,
 };
 
-#[derive(Clone, Copy)]
+/// How much one page table maps
+const TABLE_SPAN: usize = ENTRIES_PER_TABLE * PAGE_SIZE;
+/// The first directory entry that belongs to the kernel
+const KERNEL_DENTRY: usize = KERNEL_BASE / TABLE_SPAN;
+
+/// Owns the directory and the tables of the user half, they are released when it is dropped
 pub struct PageDirectory {
-    tables: *mut PageTable,
-    _marker: PhantomData<[PageTable]>,
+    entries: *mut PageTableEntry,
+    _marker: PhantomData<[PageTableEntry]>,
 }
 
 impl PageDirectory {
-    pub fn new(pte_flags: Flags) -> Self {
-        // let tables = alloc::<PageTable>(ENTRIES_PER_TABLE * ENTRY_SIZE);
-        let tables: *mut PageTable = alloc!(ENTRIES_PER_TABLE * ENTRY_SIZE);
+    /// An address space with nothing mapped below KERNEL_BASE and the kernel above it.
+    /// None if we are out of frames.
+    pub fn new() -> Option<Self> {
+        let frame = Frames::alloc_zeroed()?;
+        let this = Self {
+            entries: phys_to_virt(frame.0) as *mut PageTableEntry,
+            _marker: PhantomData,
+        };
 
-        for dentry in 0..ENTRIES_PER_TABLE {
-            unsafe {
-                tables.add(dentry).write(PageTable::new(
-                    Offset(dentry * ENTRIES_PER_TABLE * PAGE_SIZE),
-                    pte_flags,
-                ));
-            }
-        }
+        // The kernel's entries never change after boot, so a copy is as good as sharing them
+        KernelPage::get().with_rlock(|kernel| unsafe {
+            core::ptr::copy_nonoverlapping(
+                kernel.entries.add(KERNEL_DENTRY),
+                this.entries.add(KERNEL_DENTRY),
+                ENTRIES_PER_TABLE - KERNEL_DENTRY,
+            )
+        });
 
-        Self {
-            tables,
-            _marker: PhantomData,
-        }
+        Some(this)
     }
 
-    pub fn ptr(&self) -> *mut PageTable {
-        self.tables
-    }
+    /// The directory the boot code mapped the kernel with, without what it needed to get there.
+    /// It lives in the kernel image and must never be dropped.
+    pub(super) fn kernel(entries: *mut PageTableEntry) -> Self {
+        let this = Self {
+            entries,
+            _marker: PhantomData,
+        };
 
-    pub fn free(&mut self) {
-        for dentry in 0..ENTRIES_PER_TABLE {
-            let table = self.get_table(Page(dentry));
-            table.free();
+        for dentry in 0..KERNEL_DENTRY {
+            unsafe {
+                this.entries
+                    .add(dentry)
+                    .write(PageTableEntry::new(Addr(0), 0))
+            };
         }
+
+        this
     }
 
-    pub fn get_entry(&self, vaddr: Addr) -> PageTableEntry {
-        let ptr = self.ptr() as usize + ((vaddr.0 >> 12) & 0x3FF) * 4;
+    /// The physical address of the directory, what goes in CR3
+    pub fn addr(&self) -> Addr {
+        Addr(virt_to_phys(self.entries as usize))
+    }
 
-        PageTableEntry::new(Addr(ptr & 0xfffff000), (ptr & 0x00000fff) as u16)
+    fn is_loaded(&self) -> bool {
+        Paging::current().0 == self.addr().0
     }
 
-    pub fn inspect(&self, drange: Range<usize>, trange: Range<usize>) {
-        traceln!("Page Directory");
-        for i in drange {
-            unsafe {
-                trace!(
-                    "\n\nPage {} 0x{:x}\n\n",
-                    i,
-                    (*self.tables.add(i)).entries as usize
-                );
-            };
-            let page = self.get_table(Page(i));
-            let mut k = 0;
-            for j in trange.clone() {
-                let entry = page.get(Offset(j));
-                trace!("0x{:x} - 0b{:b}\t", entry.addr().0, entry.flags());
-                k += 1;
-                if k % 5 == 0 {
-                    trace!("\n");
-                    k = 0;
-                }
-            }
-        }
+    /// The table of the user half for @page, if it has one
+    fn get_table(&self, page: Page) -> Option<PageTable> {
+        let dentry = unsafe { *self.entries.add(page.0) };
+        (page.0 < KERNEL_DENTRY && dentry.flags() & PAGE_IS_PRESENT != 0)
+            .then(|| PageTable::from_entry(dentry))
     }
 
-    fn _free(self) {
-        for i in 0..ENTRIES_PER_TABLE {
-            let table = self.get_table(Page(i));
-            table.free()
+    /// Tables are only made once something is mapped in them
+    fn get_or_new_table(&mut self, page: Page) -> Option<PageTable> {
+        if let Some(table) = self.get_table(Page(page.0)) {
+            return Some(table);
         }
 
-        free!(self.tables)
-    }
+        // What user land may do is up to the entries in the table
+        let table = PageTable::new()?;
+        let flags = PAGE_IS_PRESENT | PAGE_IS_WRITABLE | PAGE_ACCESS_ALL;
+        unsafe {
+            self.entries
+                .add(page.0)
+                .write(PageTableEntry::new(table.addr(), flags))
+        };
 
-    fn get_table(&self, page: Page) -> PageTable {
-        unsafe { PageTable::from_ptr(self.tables.add(page.0)) }
+        Some(table)
     }
 
-    fn set(&mut self, vaddr: Addr, entry: PageTableEntry) {
+    /// None if a table was needed and we are out of frames
+    fn set(&mut self, vaddr: Addr, entry: PageTableEntry) -> Option<()> {
         assert!(vaddr.is_aligned());
+        assert!(
+            vaddr.0 < KERNEL_BASE,
+            "The kernel is mapped at boot: {vaddr}"
+        );
 
-        let dentry = vaddr.as_page();
-        let mut table = self.get_table(dentry);
-
-        let tentry = vaddr.as_offset();
+        let mut table = self.get_or_new_table(vaddr.as_page())?;
+        table.set(vaddr.as_offset(), entry);
+        if self.is_loaded() {
+            Paging::invalidate(vaddr);
+        }
 
-        table.set(tentry, entry)
+        Some(())
     }
 
-    pub fn map(&mut self, vaddr: Addr, paddr: Addr, flags: Flags) {
+    /// None if we are out of frames for the page table
+    pub fn map(&mut self, vaddr: Addr, paddr: Addr, flags: Flags) -> Option<()> {
         assert!(vaddr.is_aligned(), "Invalid virtual address: {vaddr}");
         assert!(paddr.is_aligned(), "Invalid physical address: {paddr}");
 
-        self.set(vaddr, PageTableEntry::new(paddr, flags));
+        self.set(vaddr, PageTableEntry::new(paddr, flags))
+    }
+
+    /// Make @vaddr fault on the next access, the frame behind it is left alone
+    pub fn unmap(&mut self, vaddr: Addr) {
+        assert!(vaddr.is_aligned(), "Invalid virtual address: {vaddr}");
+
+        if let Some(mut table) = self.get_table(vaddr.as_page()) {
+            table.set(vaddr.as_offset(), PageTableEntry::new(Addr(0), 0));
+            if self.is_loaded() {
+                Paging::invalidate(vaddr);
+            }
+        }
     }
 
-    pub fn map_range(&mut self, vstart: Addr, pstart: Addr, pend: Addr, flags: Flags) {
+    /// Unmap the pages of @start..@end, both page aligned
+    pub fn unmap_range(&mut self, start: Addr, end: Addr) {
+        assert!(end.raw() >= start.raw(), "Invalid address range");
+
+        for page in (start.raw()..end.raw()).step_by(PAGE_SIZE) {
+            self.unmap(Addr(page));
+        }
+    }
+
+    pub fn map_range(
+        &mut self,
+        vstart: Addr,
+        pstart: Addr,
+        pend: Addr,
+        flags: Flags,
+    ) -> Option<()> {
         assert!(pend.raw() >= pstart.raw(), "Invalid address range");
 
         let count = (pend.raw() - pstart.raw()) / PAGE_SIZE;
@@ -115,19 +157,160 @@ impl PageDirectory {
                 vstart.offset(page * PAGE_SIZE),
                 pstart.offset(page * PAGE_SIZE),
                 flags,
+            )?;
+        }
+
+        Some(())
+    }
+
+    /// The entry for the page @vaddr is in, if it is present
+    fn entry(&self, vaddr: Addr) -> Option<PageTableEntry> {
+        let entry = self.get_table(vaddr.as_page())?.get(vaddr.as_offset());
+        (entry.flags() & PAGE_IS_PRESENT != 0).then_some(entry)
+    }
+
+    /// Where @vaddr lands in physical memory and the flags of its page, None if it isn't mapped
+    pub fn translate(&self, vaddr: Addr) -> Option<(Addr, Flags)> {
+        let entry = self.entry(vaddr)?;
+        Some((entry.addr().offset(vaddr.0 % PAGE_SIZE), entry.flags()))
+    }
+
+    pub fn is_mapped(&self, vaddr: Addr) -> bool {
+        self.entry(vaddr).is_some()
+    }
+
+    /// Give the mapped pages of @start..@end, both page aligned, @flags instead of theirs.
+    /// The pages that aren't mapped stay that way.
+    pub fn protect(&mut self, start: Addr, end: Addr, flags: Flags) {
+        assert!(
+            start.is_aligned() && end.is_aligned(),
+            "Invalid address range"
+        );
+        assert!(end.raw() >= start.raw(), "Invalid address range");
+
+        for page in (start.raw()..end.raw()).step_by(PAGE_SIZE) {
+            let Some(entry) = self.entry(Addr(page)) else {
+                continue;
+            };
+            self.set(
+                Addr(page),
+                PageTableEntry::new(entry.addr(), flags | PAGE_IS_PRESENT),
             );
         }
     }
 
-    pub fn get_paddr(&self, vaddr: Addr) -> Addr {
-        self.get_table(vaddr.align_lower().as_page())
-            .get(vaddr.as_offset())
-            .addr()
+    /// The user half in runs of pages that map consecutive frames with the same flags
+    pub fn mapped(&self) -> MappedRanges<'_> {
+        MappedRanges {
+            directory: self,
+            next: 0,
+        }
     }
 
-    pub fn get_flags(&self, vaddr: Addr) -> Flags {
-        self.get_table(vaddr.align_lower().as_page())
-            .get(vaddr.as_offset())
-            .flags()
+    /// Trace what the user half maps, a line per run
+    pub fn dump(&self) {
+        traceln!("Page directory at {}", self.addr());
+        for range in self.mapped() {
+            traceln!(
+                "  {:08x}-{:08x} -> {:08x} {}{}{}",
+                range.start.0,
+                range.end.0,
+                range.paddr.0,
+                if range.flags & PAGE_IS_WRITABLE != 0 {
+                    'w'
+                } else {
+                    '-'
+                },
+                if range.flags & PAGE_ACCESS_ALL != 0 {
+                    'u'
+                } else {
+                    '-'
+                },
+                if range.flags & PAGE_IS_DIRTY != 0 {
+                    'd'
+                } else {
+                    '-'
+                }
+            );
+        }
+    }
+}
+
+/// Pages @start..@end mapped to the frames from @paddr on
+#[derive(Clone, Copy)]
+pub struct MappedRange {
+    pub start: Addr,
+    pub end: Addr,
+    pub paddr: Addr,
+    pub flags: Flags,
+}
+
+pub struct MappedRanges<'a> {
+    directory: &'a PageDirectory,
+    next: usize, // The next page to look at
+}
+
+impl Iterator for MappedRanges<'_> {
+    type Item = MappedRange;
+
+    fn next(&mut self) -> Option<MappedRange> {
+        // What the CPU sets on its own doesn't split a run
+        let ignored = PAGE_WAS_ACCESSED | PAGE_IS_DIRTY;
+        let mut range: Option<MappedRange> = None;
+
+        while self.next < KERNEL_BASE {
+            let vaddr = Addr(self.next);
+            let Some(table) = self.directory.get_table(vaddr.as_page()) else {
+                if range.is_some() {
+                    break;
+                }
+                self.next = (self.next | (TABLE_SPAN - 1)) + 1;
+                continue;
+            };
+
+            let entry = table.get(vaddr.as_offset());
+            let present = entry.flags() & PAGE_IS_PRESENT != 0;
+            match range.as_mut() {
+                None if present => {
+                    range = Some(MappedRange {
+                        start: vaddr,
+                        end: vaddr.offset(PAGE_SIZE),
+                        paddr: entry.addr(),
+                        flags: entry.flags(),
+                    })
+                }
+                None => {}
+                Some(range)
+                    if present
+                        && entry.flags() & !ignored == range.flags & !ignored
+                        && entry.addr().0 == range.paddr.0 + (range.end.0 - range.start.0) =>
+                {
+                    range.end = vaddr.offset(PAGE_SIZE);
+                    range.flags |= entry.flags() & ignored;
+                }
+                Some(_) => break,
+            }
+
+            self.next += PAGE_SIZE;
+        }
+
+        range
+    }
+}
+
+impl Drop for PageDirectory {
+    /// The kernel's directory is loaded instead if this one is
+    fn drop(&mut self) {
+        if self.is_loaded() {
+            KernelPage::switch();
+        }
+
+        for dentry in 0..KERNEL_DENTRY {
+            if let Some(table) = self.get_table(Page(dentry)) {
+                table.free();
+            }
+        }
+
+        Frames::free(self.addr())
     }
 }
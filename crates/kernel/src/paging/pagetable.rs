@@ -1,6 +1,7 @@
 use core::marker::PhantomData;
 
-use super::{Addr, Flags, Offset, ENTRIES_PER_TABLE, PAGE_SIZE};
+use super::{frame::Frames, Addr, Flags, Offset, ENTRIES_PER_TABLE};
+use crate::memory::{phys_to_virt, virt_to_phys};
 
 #[derive(Debug, Clone, Copy)]
 pub struct PageTableEntry(usize);
@@ -28,31 +29,29 @@ impl PageTableEntry {
 }
 
 impl PageTable {
-    pub fn new(offset: Offset, flags: Flags) -> Self {
-        let entries: *mut PageTableEntry = alloc!(ENTRIES_PER_TABLE * ENTRY_SIZE);
-        for entry in 0..ENTRIES_PER_TABLE {
-            let addr = Addr(offset.0 + entry * PAGE_SIZE);
-            unsafe {
-                entries.add(entry).write(PageTableEntry::new(addr, flags));
-            }
-        }
+    /// A table where nothing is mapped, None if we are out of frames
+    pub fn new() -> Option<Self> {
+        let frame = Frames::alloc_zeroed()?;
 
-        Self {
-            entries: (entries as usize | flags as usize) as *mut PageTableEntry,
+        Some(Self {
+            entries: phys_to_virt(frame.0) as *mut PageTableEntry,
             _marker: PhantomData,
-        }
+        })
     }
 
     pub fn free(self) {
-        free!(self.entries);
+        Frames::free(self.addr());
+    }
+
+    /// The physical address of the table, what the directory points at
+    pub fn addr(&self) -> Addr {
+        Addr(virt_to_phys(self.entries as usize))
     }
 
-    /// # Safety
-    pub unsafe fn from_ptr(ptr: *mut PageTable) -> Self {
-        let table_with_flags = unsafe { *ptr };
-        let table_ptr = table_with_flags.entries as usize & 0xfffff000;
+    /// The table a directory @entry points at
+    pub fn from_entry(entry: PageTableEntry) -> Self {
         Self {
-            entries: table_ptr as *mut PageTableEntry,
+            entries: phys_to_virt(entry.addr().0) as *mut PageTableEntry,
             _marker: PhantomData,
         }
     }
@@ -1,5 +1,7 @@
 use crate::boxed::Vec;
 
+pub const MAX_PATH: usize = 128;
+
 pub struct Path<'a> {
     pub disk_id: Option<u32>, // If this is None, the path is empty or invalid
     parts: Vec<&'a str>,
@@ -12,7 +14,9 @@ impl<'a> Path<'a> {
             _ => None,
         };
 
-        let parts = path[2..]
+        let parts = path
+            .get(2..)
+            .unwrap_or("")
             .split('/')
             .filter(|part| !part.is_empty())
             .collect();
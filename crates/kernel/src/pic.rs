@@ -0,0 +1,126 @@
+use crate::io::{insb, outb};
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+const ICW1_ICW4: u8 = 1 << 0; // ICW4 will be sent
+const ICW1_INIT: u8 = 1 << 4;
+const ICW4_8086: u8 = 1 << 0;
+const OCW3_READ_ISR: u8 = 0x0B;
+const EOI: u8 = 0x20;
+
+/// Vectors the IRQs are remapped to, clear of the CPU exceptions
+pub const PIC1_OFFSET: u8 = 0x20;
+pub const PIC2_OFFSET: u8 = 0x28;
+const IRQS_PER_PIC: u8 = 8;
+
+pub const IRQ_TIMER: u8 = 0;
+pub const IRQ_KEYBOARD: u8 = 1;
+pub const IRQ_CASCADE: u8 = 2; // The slave is wired to this line on the master
+pub const IRQ_COM1: u8 = 4;
+pub const IRQ_ATA_PRIMARY: u8 = 14;
+
+// The lowest priority line on each chip, where spurious interrupts show up
+const IRQ_SPURIOUS_MASTER: u8 = 7;
+const IRQ_SPURIOUS_SLAVE: u8 = 15;
+
+/// The cascaded 8259 pair
+pub struct PIC;
+impl PIC {
+    /// Remap master and slave after the CPU exceptions and mask every IRQ.
+    /// Drivers unmask the lines they handle.
+    pub fn init() {
+        outb(PIC1_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        outb(PIC2_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+
+        outb(PIC1_DATA, PIC1_OFFSET);
+        io_wait();
+        outb(PIC2_DATA, PIC2_OFFSET);
+        io_wait();
+
+        // Tell the master where the slave is, and the slave its identity
+        outb(PIC1_DATA, 1 << IRQ_CASCADE);
+        io_wait();
+        outb(PIC2_DATA, IRQ_CASCADE);
+        io_wait();
+
+        outb(PIC1_DATA, ICW4_8086);
+        io_wait();
+        outb(PIC2_DATA, ICW4_8086);
+        io_wait();
+
+        outb(PIC1_DATA, !(1 << IRQ_CASCADE));
+        outb(PIC2_DATA, 0xFF);
+    }
+
+    pub fn mask(irq: u8) {
+        let (port, bit) = Self::line(irq);
+        outb(port, insb(port) | (1 << bit));
+    }
+
+    pub fn unmask(irq: u8) {
+        let (port, bit) = Self::line(irq);
+        outb(port, insb(port) & !(1 << bit));
+    }
+
+    /// The IRQ that is delivered on @vector, if any
+    pub fn irq(vector: u8) -> Option<u8> {
+        vector
+            .checked_sub(PIC1_OFFSET)
+            .filter(|irq| *irq < 2 * IRQS_PER_PIC)
+    }
+
+    /// Send the EOI for @vector, exceptions and software interrupts are left alone.
+    /// Returns false if the interrupt was spurious and must not be serviced.
+    pub fn acknowledge(vector: u8) -> bool {
+        let Some(irq) = Self::irq(vector) else {
+            return true;
+        };
+
+        if Self::is_spurious(irq) {
+            // The master did see the slave raise the cascade line
+            if irq == IRQ_SPURIOUS_SLAVE {
+                outb(PIC1_COMMAND, EOI);
+            }
+            return false;
+        }
+
+        if irq >= IRQS_PER_PIC {
+            outb(PIC2_COMMAND, EOI);
+        }
+        outb(PIC1_COMMAND, EOI);
+
+        true
+    }
+
+    /// A spurious IRQ is not in service, the line dropped before it was acknowledged
+    fn is_spurious(irq: u8) -> bool {
+        let command = match irq {
+            IRQ_SPURIOUS_MASTER => PIC1_COMMAND,
+            IRQ_SPURIOUS_SLAVE => PIC2_COMMAND,
+            _ => return false,
+        };
+
+        outb(command, OCW3_READ_ISR);
+        insb(command) & (1 << (irq % IRQS_PER_PIC)) == 0
+    }
+
+    fn line(irq: u8) -> (u16, u8) {
+        assert!(irq < 2 * IRQS_PER_PIC, "Invalid IRQ: {irq}");
+
+        if irq < IRQS_PER_PIC {
+            (PIC1_DATA, irq)
+        } else {
+            (PIC2_DATA, irq - IRQS_PER_PIC)
+        }
+    }
+}
+
+/// Give the PIC time to settle, port 0x80 is unused
+fn io_wait() {
+    outb(0x80, 0);
+}
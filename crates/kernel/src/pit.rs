@@ -0,0 +1,82 @@
+use core::arch::naked_asm;
+
+use global::global;
+use interrupts::interrupt_handler;
+
+use crate::{
+    cpu::InterruptFrame,
+    io::outb,
+    pic::{IRQ_TIMER, PIC, PIC1_OFFSET},
+    process::Scheduler,
+};
+
+const PIT_FREQUENCY: usize = 1193182;
+const PIT_CHANNEL0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+
+// Channel 0, access lobyte/hibyte, mode 3 (square wave), binary
+const PIT_MODE_SQUARE_WAVE: u8 = 0b00110110;
+
+pub const TIMER_HZ: usize = 100;
+
+global!(Ticks, usize, 0, "TICKS");
+
+pub struct PIT;
+impl PIT {
+    /// Program channel 0 of the 8253/8254 to fire IRQ0 @hz times a second.
+    pub fn init(hz: usize) {
+        let divisor = PIT_FREQUENCY / hz;
+        assert!(
+            divisor > 0 && divisor <= u16::MAX as usize,
+            "Invalid timer frequency"
+        );
+
+        outb(PIT_COMMAND, PIT_MODE_SQUARE_WAVE);
+        outb(PIT_CHANNEL0, (divisor & 0xFF) as u8);
+        outb(PIT_CHANNEL0, (divisor >> 8) as u8);
+
+        PIC::unmask(IRQ_TIMER);
+    }
+
+    pub fn ticks() -> usize {
+        Ticks::get().with_rlock(|ticks| *ticks)
+    }
+}
+
+#[interrupt_handler(0x20)]
+pub extern "C" fn entry_timer() {
+    unsafe {
+        naked_asm!(
+            r#"
+                cli
+                push 0
+                pushad
+
+                push esp
+                call timer_handler
+                add esp, 4
+
+                popad
+                add esp, 4
+                iretd
+            "#,
+        )
+    };
+}
+
+/// # Safety
+///  Unsafe because derefences raw pointer from CPU
+#[no_mangle]
+pub unsafe extern "C" fn timer_handler(frame: *const InterruptFrame) {
+    Ticks::set(|ticks| *ticks += 1);
+    PIC::acknowledge(PIC1_OFFSET + IRQ_TIMER);
+
+    let frame = unsafe { *frame };
+
+    // The kernel is not re-entrant, only ever preempt user land
+    if frame.cs & 0x3 != 0x3 {
+        return;
+    }
+
+    Scheduler::preempt(frame);
+}
@@ -1,23 +1,35 @@
-use core::marker::PhantomData;
-
 use global::global;
 
-use crate::boxed::Array;
+use crate::boxed::Box;
+use crate::cmdline::Options;
 use crate::cpu::CPU;
-use crate::fs::{FileMode, VFS};
+use crate::fs::{FileDescriptor, FileMode, VFS};
 use crate::loader;
 use crate::loader::elf::Elf;
-use crate::paging::{Addr, PAGE_SIZE};
-use crate::paging::{PAGE_ACCESS_ALL, PAGE_IS_PRESENT, PAGE_IS_WRITABLE};
-use crate::path::Path;
+use crate::paging::{frame::Frames, Addr, PAGE_SIZE};
+use crate::path::{Path, MAX_PATH};
 use crate::sync::{Shared, Weak};
+use crate::syscalls::errno::{EAGAIN, EINVAL, ENOENT, ENOEXEC, ENOMEM};
 use crate::task::Task;
+use crate::tty::Console;
+
+mod region;
+mod scheduler;
+use region::Region;
+pub use scheduler::Scheduler;
 
-const USER_STACK_SIZE: usize = 16 * 1024;
 const USER_STACK_START: usize = 0x3FF000;
-const USER_STACK_END: usize = USER_STACK_START - USER_STACK_SIZE;
+/// How far the user stack may grow down from USER_STACK_START
+pub const USER_STACK_LIMIT: usize = 256 * 1024;
 const USER_VIRTUAL_START: usize = 0x400000;
 const MAX_PROCESSES: usize = 12;
+const MAX_EXIT_STATUSES: usize = 2 * MAX_PROCESSES;
+const MAX_REGIONS: usize = 8;
+const IDLE_PROCESS: usize = 0;
+pub const MAX_FILES: usize = 16;
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
 
 global!(
     ProcessList,
@@ -26,6 +38,13 @@ global!(
     "PROCESSES"
 );
 global!(Current, usize, 0, "CURRENT_PROCESS");
+global!(NextPid, usize, 0, "NEXT_PID");
+global!(
+    ExitStatuses,
+    [Option<ExitStatus>; MAX_EXIT_STATUSES],
+    [None; MAX_EXIT_STATUSES],
+    "EXIT_STATUSES"
+);
 
 impl Current {
     pub fn assign(id: usize) {
@@ -51,13 +70,44 @@ impl CurrentProcess {
 #[derive(Debug)]
 pub enum ProcessError {
     InvalidFormat,
+    NotFound,
+    TooManyProcesses,
+    OutOfMemory,
     Other,
 }
 
-// Pointer to the data and its size
-enum ProcessData {
-    Binary(Array<u8>, PhantomData<[u8]>),
-    Elf(Elf),
+impl ProcessError {
+    pub fn errno(&self) -> usize {
+        match self {
+            ProcessError::InvalidFormat => ENOEXEC,
+            ProcessError::NotFound => ENOENT,
+            ProcessError::TooManyProcesses => EAGAIN,
+            ProcessError::OutOfMemory => ENOMEM,
+            ProcessError::Other => EINVAL,
+        }
+    }
+}
+
+/// What a blocked process is waiting for
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Block {
+    Child,
+    Input,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Ready,
+    Blocked(Block),
+    Dead(usize), // The process has exited with a code and should be cleaned-up
+}
+
+/// What is left of a process once it has been reaped
+#[derive(Clone, Copy)]
+pub struct ExitStatus {
+    pub pid: usize,
+    pub parent: Option<usize>,
+    pub code: usize,
 }
 
 pub struct Processes;
@@ -90,69 +140,293 @@ impl Processes {
         unsafe {
             ProcessList::get_mut().with_wlock(|list| -> Option<usize> {
                 let id = Self::find_slot()?;
-
-                process.with_wlock(|process| process.id = id);
+                let pid = NextPid::get_mut().with_wlock(|pid| {
+                    *pid += 1;
+                    *pid - 1
+                });
+
+                process.with_wlock(|process| {
+                    process.id = id;
+                    process.pid = pid;
+                });
                 list[id] = Some(process);
                 Some(id)
             })
         }
     }
+
+    /// Release the slot of every dead process and record its exit status.
+    ///
+    /// Dropping the last reference to a process frees everything it owns. Its page
+    /// directory switches to the kernel's first if it is loaded, so this can be called
+    /// from any address space, like the one the scheduler was entered from.
+    pub fn reap() {
+        for id in 0..MAX_PROCESSES {
+            let dead = ProcessList::get_mut().with_wlock(|list| {
+                let status = list[id]
+                    .as_ref()?
+                    .with_rlock(|process| match process.state {
+                        ProcessState::Dead(code) => Some(ExitStatus {
+                            pid: process.pid,
+                            parent: process.parent,
+                            code,
+                        }),
+                        _ => None,
+                    })?;
+
+                Some((status, list[id].take()))
+            });
+
+            if let Some((status, process)) = dead {
+                Self::record(status);
+
+                // This should be the last reference
+                drop(process);
+
+                // Anything of the process's still listed after this has leaked
+                #[cfg(feature = "heap-debug")]
+                crate::heap::KernelHeap::dump_allocations();
+
+                Self::orphan(status.pid);
+                if let Some(parent) = status.parent {
+                    Self::wake(parent, Block::Child);
+                }
+            }
+        }
+    }
+
+    /// Forget about the children of @parent, nobody will be collecting them.
+    fn orphan(parent: usize) {
+        ProcessList::get_mut().with_wlock(|list| {
+            for process in list.iter_mut().flatten() {
+                process.with_wlock(|process| {
+                    if process.parent == Some(parent) {
+                        process.parent = None;
+                    }
+                });
+            }
+        });
+
+        ExitStatuses::set(|statuses| {
+            for slot in statuses.iter_mut() {
+                if slot.is_some_and(|status| status.parent == Some(parent)) {
+                    *slot = None;
+                }
+            }
+        });
+    }
+
+    /// Make the process @pid runnable again if it is blocked on @reason.
+    pub fn wake(pid: usize, reason: Block) {
+        ProcessList::get_mut().with_wlock(|list| {
+            for process in list.iter_mut().flatten() {
+                process.with_wlock(|process| {
+                    if process.pid == pid && process.state == ProcessState::Blocked(reason) {
+                        process.state = ProcessState::Ready;
+                    }
+                });
+            }
+        });
+    }
+
+    /// Make every process blocked on @reason runnable again.
+    pub fn wake_all(reason: Block) {
+        ProcessList::get_mut().with_wlock(|list| {
+            for process in list.iter_mut().flatten() {
+                process.with_wlock(|process| {
+                    if process.state == ProcessState::Blocked(reason) {
+                        process.state = ProcessState::Ready;
+                    }
+                });
+            }
+        });
+    }
+
+    /// Does @parent have a child, dead or alive, that hasn't been collected yet?
+    /// If @pid is None any child will do.
+    pub fn has_child(parent: usize, pid: Option<usize>) -> bool {
+        let matches = |child_pid: usize, child_parent: Option<usize>| {
+            child_parent == Some(parent) && pid.is_none_or(|pid| child_pid == pid)
+        };
+
+        let alive = ProcessList::get().with_rlock(|list| {
+            list.iter()
+                .flatten()
+                .any(|process| process.with_rlock(|process| matches(process.pid, process.parent)))
+        });
+
+        alive
+            || ExitStatuses::get().with_rlock(|statuses| {
+                statuses
+                    .iter()
+                    .flatten()
+                    .any(|status| matches(status.pid, status.parent))
+            })
+    }
+
+    fn record(status: ExitStatus) {
+        ExitStatuses::set(|statuses| {
+            if let Some(slot) = statuses.iter_mut().find(|slot| slot.is_none()) {
+                *slot = Some(status);
+                return;
+            }
+
+            // Nobody is collecting, forget the oldest one
+            let oldest = statuses
+                .iter_mut()
+                .min_by_key(|slot| slot.map_or(usize::MAX, |status| status.pid))
+                .unwrap();
+            *oldest = Some(status);
+        })
+    }
+
+    /// Take the exit status of a dead child of @parent, if @pid is None any child will do.
+    pub fn collect(parent: usize, pid: Option<usize>) -> Option<ExitStatus> {
+        ExitStatuses::get_mut().with_wlock(|statuses| {
+            statuses
+                .iter_mut()
+                .find(|slot| {
+                    slot.is_some_and(|status| {
+                        status.parent == Some(parent) && pid.is_none_or(|pid| status.pid == pid)
+                    })
+                })?
+                .take()
+        })
+    }
 }
 
 struct ProcessBare {
     task: Task,
-    data: ProcessData,
-    bss: Option<*const ()>,
+    regions: [Option<Region>; MAX_REGIONS],
+}
+
+impl ProcessBare {
+    fn new(entry: Option<usize>) -> Result<Self, ProcessError> {
+        Ok(Self {
+            task: Task::new(Weak::new(), entry).ok_or(ProcessError::OutOfMemory)?,
+            regions: [None; MAX_REGIONS],
+        })
+    }
+
+    /// Make @region part of the program and copy @data to @vaddr in it.
+    /// A page that is also in an earlier region stays backed by the same frame,
+    /// each region holds a reference to it.
+    fn add_region(&mut self, region: Region, vaddr: Addr, data: &[u8]) -> Result<(), ProcessError> {
+        let slot = self
+            .regions
+            .iter()
+            .position(|slot| slot.is_none())
+            .ok_or(ProcessError::InvalidFormat)?;
+
+        let directory = &mut self.task.page_directory;
+        for page in region.pages() {
+            match self
+                .regions
+                .iter()
+                .flatten()
+                .find(|other| other.contains(page))
+            {
+                Some(other) => {
+                    let frame = other
+                        .back(directory, page)
+                        .ok_or(ProcessError::OutOfMemory)?;
+                    Frames::share(frame);
+                    directory
+                        .map(page, frame, other.flags() | region.flags())
+                        .ok_or(ProcessError::OutOfMemory)?;
+                }
+                None => directory.unmap(page),
+            }
+        }
+        self.regions[slot] = Some(region);
+
+        region
+            .load(directory, vaddr, data)
+            .ok_or(ProcessError::OutOfMemory)
+    }
+
+    /// Release the frames of a program that never became a process
+    fn free(&self) {
+        for region in self.regions.iter().flatten() {
+            region.free(&self.task.page_directory);
+        }
+    }
 }
 
 pub struct Process {
-    id: usize,
+    id: usize,  // The slot in the process list
+    pid: usize, // Unique for the lifetime of the kernel
+    parent: Option<usize>,
     task: Shared<Task>,
-    // TODO: Track allocations
-    data: ProcessData,
-    bss: Option<*const ()>,
-    stack: *const (),
-    _bss_marker: PhantomData<[u8]>,
-    _stack_marker: PhantomData<[u8]>,
-
-    _mark_dead: bool, // If true, the process is effectively dead and should be cleaned-up
+    regions: [Option<Region>; MAX_REGIONS],
+    stack: Region,
+
+    files: [Option<Box<dyn FileDescriptor>>; MAX_FILES],
+    state: ProcessState,
 }
 
 impl Process {
-    pub fn new(filename: &str) -> Result<Shared<Process>, ProcessError> {
-        let bare = match Self::new_elf(filename) {
-            Err(ProcessError::InvalidFormat) => Self::new_binary(filename)?,
-            bare => bare?,
-        };
+    pub fn new(filename: &str, parent: Option<usize>) -> Result<Shared<Process>, ProcessError> {
+        let mut process = Self::from_bare(Self::load(filename)?);
+        process.with_wlock(|process| process.parent = parent);
 
-        let process = Self::from_bare(bare);
-
-        if let Some(id) = Processes::insert(process.clone()) {
-            Current::assign(id);
-        }
+        Processes::insert(process.clone()).ok_or(ProcessError::TooManyProcesses)?;
 
         Ok(process)
     }
 
+    /// Replace the program @this is running with @filename.
+    /// The process keeps its identity and open files, the old image is released.
+    pub fn replace(mut this: Shared<Process>, filename: &str) -> Result<(), ProcessError> {
+        let mut fresh = Self::from_bare(Self::load(filename)?);
+
+        fresh.with_wlock(|fresh| {
+            this.with_wlock(|this| {
+                core::mem::swap(&mut this.task, &mut fresh.task);
+                core::mem::swap(&mut this.regions, &mut fresh.regions);
+                core::mem::swap(&mut this.stack, &mut fresh.stack);
+            })
+        });
+
+        let weak = Shared::weak(&this);
+        this.with_wlock(|this| this.task.with_wlock(|task| task.process = weak));
+
+        // This takes the old image with it
+        drop(fresh);
+
+        Ok(())
+    }
+
+    /// Programs that don't name a disk are looked up on the root one
+    fn load(filename: &str) -> Result<ProcessBare, ProcessError> {
+        let mut buf = [0; MAX_PATH];
+        let filename = Options::resolve(filename, &mut buf).ok_or(ProcessError::NotFound)?;
+
+        match Self::new_elf(filename) {
+            Err(ProcessError::InvalidFormat) => Self::new_binary(filename),
+            bare => bare,
+        }
+    }
+
     fn from_bare(mut bare: ProcessBare) -> Shared<Self> {
-        // Stack
-        let stack: *const () = alloc!(USER_STACK_SIZE);
-        bare.task.page_directory.map_range(
-            Addr(USER_STACK_END),
-            Addr(stack as usize),
-            Addr(stack as usize + USER_STACK_SIZE).align_upper(),
-            PAGE_IS_PRESENT | PAGE_ACCESS_ALL | PAGE_IS_WRITABLE,
-        );
+        // The stack grows on demand
+        let stack = Region::new(USER_STACK_START - USER_STACK_LIMIT, USER_STACK_START, true);
+        stack.unmap(&mut bare.task.page_directory);
+
+        let mut files = [const { None }; MAX_FILES];
+        files[STDIN] = Some(Box::new(Console::Input) as Box<dyn FileDescriptor>);
+        files[STDOUT] = Some(Box::new(Console::Output) as Box<dyn FileDescriptor>);
+        files[STDERR] = Some(Box::new(Console::Output) as Box<dyn FileDescriptor>);
 
         let mut process = Shared::new(Self {
             id: 0,
+            pid: 0,
+            parent: None,
             task: Shared::new(bare.task),
-            data: bare.data,
-            bss: bare.bss,
+            regions: bare.regions,
             stack,
-            _bss_marker: PhantomData,
-            _stack_marker: PhantomData,
-            _mark_dead: false,
+            files,
+            state: ProcessState::Ready,
         });
 
         let weak = Shared::weak(&process);
@@ -162,127 +436,195 @@ impl Process {
         process
     }
 
+    /// Back the page holding @vaddr with a zeroed page, if it is in one of
+    /// the demand-paged regions of @this.
+    /// Returns false if @vaddr is not somewhere the process may touch.
+    pub fn fault_in(mut this: Shared<Process>, vaddr: Addr) -> bool {
+        this.with_wlock(|process| {
+            let Some(region) = process
+                .regions
+                .iter()
+                .flatten()
+                .chain(core::iter::once(&process.stack))
+                .find(|region| region.contains(vaddr))
+            else {
+                return false;
+            };
+
+            process.task.with_wlock(|task| {
+                region
+                    .back(&mut task.page_directory, vaddr.align_lower())
+                    .is_some()
+            })
+        })
+    }
+
+    /// Make @proc the current process and drop into it.
     pub fn exec(proc: Shared<Self>) {
-        let task = proc.with_rlock(|inner| inner.task());
+        let (id, task) = proc.with_rlock(|inner| (inner.id, inner.task()));
+        Current::assign(id);
+
+        // We never come back here, so don't hold on to the process
+        drop(proc);
         unsafe { CPU::return_to_task(task) }
     }
 
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn pid(&self) -> usize {
+        self.pid
+    }
+
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    pub fn state(&self) -> ProcessState {
+        self.state
+    }
+
     pub fn task(&self) -> Shared<Task> {
         self.task.clone()
     }
 
-    pub fn idle() -> Shared<Process> {
-        let mut task = Task::new(Weak::new(), None);
+    pub fn is_runnable(&self) -> bool {
+        self.state == ProcessState::Ready
+    }
 
-        let s = &[235, 254];
-        let program_data: Array<u8> = Array::from(&s[..]);
+    /// Put @file in the lowest free slot and return it, None if the table is full.
+    pub fn add_file(&mut self, file: Box<dyn FileDescriptor>) -> Option<usize> {
+        let fd = self.files.iter().position(|slot| slot.is_none())?;
+        self.files[fd] = Some(file);
+        Some(fd)
+    }
 
-        // Map the memory
-        {
-            let directory = &mut task.page_directory;
-            // Code
-            directory.map_range(
-                Addr(USER_VIRTUAL_START),
-                Addr(program_data.as_ptr() as usize),
-                Addr(program_data.as_ptr() as usize + 2).align_upper(),
-                PAGE_IS_PRESENT | PAGE_ACCESS_ALL,
-            );
+    pub fn file(&self, fd: usize) -> Option<&dyn FileDescriptor> {
+        self.files.get(fd)?.as_deref()
+    }
+
+    pub fn file_mut(&mut self, fd: usize) -> Option<&mut dyn FileDescriptor> {
+        Some(&mut **self.files.get_mut(fd)?.as_mut()?)
+    }
+
+    /// Returns the file that was open at @fd, dropping it closes it.
+    pub fn remove_file(&mut self, fd: usize) -> Option<Box<dyn FileDescriptor>> {
+        self.files.get_mut(fd)?.take()
+    }
+
+    /// The idle process spins in user land, so the timer can always preempt it.
+    /// It takes the first free slot, so it should be created before anything else.
+    pub fn idle() -> Result<Shared<Process>, ProcessError> {
+        // jmp $
+        let program = &[235, 254];
+
+        let mut bare = ProcessBare::new(None)?;
+        let code = Region::new(USER_VIRTUAL_START, USER_VIRTUAL_START + PAGE_SIZE, false);
+        if let Err(err) = bare.add_region(code, Addr(USER_VIRTUAL_START), program) {
+            bare.free();
+            return Err(err);
         }
 
-        let bare = ProcessBare {
-            task,
-            data: ProcessData::Binary(program_data, PhantomData),
-            bss: None,
-        };
+        let process = Self::from_bare(bare);
+
+        Processes::insert(process.clone()).ok_or(ProcessError::TooManyProcesses)?;
 
-        Self::from_bare(bare)
+        Ok(process)
     }
 
     fn new_elf(filename: &str) -> Result<ProcessBare, ProcessError> {
-        let elf = match Elf::load(filename) {
+        let mut elf = match Elf::load(filename) {
+            Ok(elf) => elf,
             Err(loader::Error::BadFormat) => return Err(ProcessError::InvalidFormat),
-            a => a.unwrap(),
+            Err(loader::Error::NotFound) => return Err(ProcessError::NotFound),
         };
 
-        let mut task = Task::new(Weak::new(), Some(elf.entry_point()));
-
-        // Map the memory
-        let bss = {
-            let mut bss: *const u8 = core::ptr::null();
-            let directory = &mut task.page_directory;
-            for pheader in &elf.pheaders() {
-                let flags = {
-                    let mut f = PAGE_IS_PRESENT | PAGE_ACCESS_ALL;
-                    if pheader.is_writable() {
-                        f |= PAGE_IS_WRITABLE;
-                    }
-                    f
-                };
+        let mut bare = ProcessBare::new(Some(elf.entry_point()))?;
+        let loaded = Self::load_elf(&mut bare, &elf);
 
-                if pheader.vaddr() % PAGE_SIZE != 0 {
-                    continue;
-                }
+        // Everything is copied out of the file by now
+        elf.free();
 
-                // BSS Section
-                if pheader.filesz() == 0 && pheader.memsz() > 0 {
-                    assert!(bss.is_null(), "Many BSS sections? :O");
-                    bss = alloc!(pheader.memsz());
-                }
+        match loaded {
+            Ok(()) => Ok(bare),
+            Err(err) => {
+                bare.free();
+                Err(err)
+            }
+        }
+    }
 
-                directory.map_range(
-                    Addr(pheader.vaddr()).align_lower(),
-                    Addr(pheader.paddr()).align_lower(),
-                    Addr(pheader.paddr() + pheader.memsz()).align_upper(),
-                    flags,
-                )
+    /// Copy the loadable segments into frames, what isn't in the file is zero-filled on demand
+    fn load_elf(bare: &mut ProcessBare, elf: &Elf) -> Result<(), ProcessError> {
+        for pheader in &elf.pheaders() {
+            if !pheader.is_loadable() {
+                continue;
             }
-            bss
-        };
 
-        Ok(ProcessBare {
-            task,
-            data: ProcessData::Elf(elf),
-            bss: Some(bss as *const ()),
-        })
+            let data = elf.data(pheader).ok_or(ProcessError::InvalidFormat)?;
+            let end = pheader
+                .vaddr()
+                .checked_add(pheader.memsz())
+                .filter(|_| pheader.filesz() <= pheader.memsz())
+                .ok_or(ProcessError::InvalidFormat)?;
+
+            let region = Region::new(
+                Addr(pheader.vaddr()).align_lower().0,
+                Addr(end).align_upper().0,
+                pheader.is_writable(),
+            );
+            bare.add_region(region, Addr(pheader.vaddr()), data)?;
+        }
+
+        Ok(())
     }
 
     fn new_binary(filename: &str) -> Result<ProcessBare, ProcessError> {
-        let fd = VFS::open(Path::new(filename), FileMode::ReadOnly).unwrap();
+        let fd = VFS::open(Path::new(filename), FileMode::READ_ONLY)
+            .map_err(|_| ProcessError::NotFound)?;
         let size = fd.stat().size;
 
-        let program_data = fd.read_all().unwrap();
+        let mut program_data = fd.read_all().map_err(|_| ProcessError::NotFound)?;
 
-        let mut task = Task::new(Weak::new(), None);
-
-        // Map the memory
-        {
-            let directory = &mut task.page_directory;
-            // Code
-            directory.map_range(
-                Addr(USER_VIRTUAL_START),
-                Addr(program_data.as_ptr() as usize),
-                Addr(program_data.as_ptr() as usize + size).align_upper(),
-                PAGE_IS_PRESENT | PAGE_ACCESS_ALL,
-            );
+        let mut bare = ProcessBare::new(None)?;
+        let code = Region::new(
+            USER_VIRTUAL_START,
+            Addr(USER_VIRTUAL_START + size).align_upper().0,
+            false,
+        );
+        let loaded = bare.add_region(code, Addr(USER_VIRTUAL_START), &program_data[..size]);
+        program_data.free();
+
+        match loaded {
+            Ok(()) => Ok(bare),
+            Err(err) => {
+                bare.free();
+                Err(err)
+            }
         }
-
-        Ok(ProcessBare {
-            task,
-            data: ProcessData::Binary(program_data, PhantomData),
-            bss: None,
-        })
     }
 
     /// This marks the process as dead.
     /// Touching it after this is undefined behaviour.
-    pub fn mark_dead(mut this: Shared<Process>, _: usize) {
-        this.with_wlock(|process| process._mark_dead = true);
+    ///
+    /// This is called from the syscall handler while the process is still current
+    /// and cannot block, so the actual cleanup is deferred to `Processes::reap`
+    /// which the scheduler runs before switching.
+    pub fn mark_dead(mut this: Shared<Process>, code: usize) {
+        this.with_wlock(|process| process.state = ProcessState::Dead(code));
+    }
+}
 
-        // TODO: How do we clean up the memory of the process?
-        //  This is called in the syscall handler so cannot block
-        // Make a garbage-collecting worker thread.
+impl Drop for Process {
+    fn drop(&mut self) {
+        self.task.with_rlock(|task| {
+            for region in self.regions.iter().flatten() {
+                region.free(&task.page_directory);
+            }
+            self.stack.free(&task.page_directory);
+        });
 
-        // TODO: Change to a more reasonable process.
-        Current::assign(0);
+        // The task and its page directory go with the last reference to it
     }
 }
@@ -0,0 +1,94 @@
+use crate::memory::phys_to_virt;
+use crate::paging::{frame::Frames, pagedirectory::PageDirectory, Addr, Flags, PAGE_SIZE};
+use crate::paging::{PAGE_ACCESS_ALL, PAGE_IS_PRESENT, PAGE_IS_WRITABLE};
+
+/// User memory backed by frames, pages that aren't loaded are zero-filled once touched
+#[derive(Clone, Copy)]
+pub struct Region {
+    start: usize,
+    end: usize,
+    writable: bool,
+}
+
+impl Region {
+    /// @start and @end must be page aligned
+    pub fn new(start: usize, end: usize, writable: bool) -> Self {
+        assert!(
+            Addr(start).is_aligned() && Addr(end).is_aligned() && start <= end,
+            "Invalid region"
+        );
+
+        Self {
+            start,
+            end,
+            writable,
+        }
+    }
+
+    pub fn contains(&self, vaddr: Addr) -> bool {
+        (self.start..self.end).contains(&vaddr.0)
+    }
+
+    pub fn flags(&self) -> Flags {
+        let mut flags = PAGE_IS_PRESENT | PAGE_ACCESS_ALL;
+        if self.writable {
+            flags |= PAGE_IS_WRITABLE;
+        }
+        flags
+    }
+
+    pub fn pages(&self) -> impl Iterator<Item = Addr> {
+        (self.start..self.end).step_by(PAGE_SIZE).map(Addr)
+    }
+
+    /// Make sure nothing is mapped yet, so the first access faults
+    pub fn unmap(&self, directory: &mut PageDirectory) {
+        directory.unmap_range(Addr(self.start), Addr(self.end));
+    }
+
+    /// The frame behind @page, a zeroed one is mapped in if there is none yet.
+    /// None if we are out of frames.
+    pub fn back(&self, directory: &mut PageDirectory, page: Addr) -> Option<Addr> {
+        if let Some((paddr, _)) = directory.translate(page) {
+            return Some(paddr);
+        }
+
+        let frame = Frames::alloc_zeroed()?;
+        if directory.map(page, frame, self.flags()).is_none() {
+            Frames::free(frame);
+            return None;
+        }
+
+        Some(frame)
+    }
+
+    /// Copy @data to @vaddr, backing the pages it lands on
+    pub fn load(&self, directory: &mut PageDirectory, vaddr: Addr, data: &[u8]) -> Option<()> {
+        let mut done = 0;
+        while done < data.len() {
+            let vaddr = vaddr.0 + done;
+            let n = (PAGE_SIZE - vaddr % PAGE_SIZE).min(data.len() - done);
+            let frame = self.back(directory, Addr(vaddr).align_lower())?;
+
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data[done..].as_ptr(),
+                    phys_to_virt(frame.0 + vaddr % PAGE_SIZE) as *mut u8,
+                    n,
+                )
+            };
+            done += n;
+        }
+
+        Some(())
+    }
+
+    /// Drop the frames that back the region
+    pub fn free(&self, directory: &PageDirectory) {
+        for page in self.pages() {
+            if let Some((paddr, _)) = directory.translate(page) {
+                Frames::free(paddr);
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::cpu::InterruptFrame;
+use crate::task::{CurrentTask, Task};
+
+use super::{
+    Block, Current, CurrentProcess, Process, ProcessList, ProcessState, Processes, IDLE_PROCESS,
+    MAX_PROCESSES,
+};
+
+// int 0x80
+const SYSCALL_INSTRUCTION_SIZE: usize = 2;
+
+/// Round-robin scheduler over the `ProcessList`.
+///
+/// The idle process is only picked when nothing else is runnable.
+pub struct Scheduler;
+impl Scheduler {
+    /// Save the interrupted user context and switch to the next runnable process.
+    pub fn preempt(frame: InterruptFrame) -> ! {
+        Task::save(CurrentTask::get(), frame);
+
+        Self::schedule()
+    }
+
+    /// Resume the next runnable process, this never returns.
+    ///
+    /// The caller is responsible for having saved the current context.
+    pub fn schedule() -> ! {
+        Processes::reap();
+
+        let next = Self::next().expect("Nothing to schedule");
+        let process = Processes::get(next).unwrap();
+
+        Process::exec(process);
+
+        unreachable!()
+    }
+
+    /// Put the current process to sleep until it is woken up for @reason.
+    ///
+    /// This must be called from a syscall, which is restarted from scratch once
+    /// the process runs again, so it gets to re-check whatever it was waiting for.
+    pub fn block(reason: Block) -> ! {
+        CurrentTask::get().with_wlock(|task| task.registers.ip -= SYSCALL_INSTRUCTION_SIZE);
+        CurrentProcess::get().with_wlock(|process| process.state = ProcessState::Blocked(reason));
+
+        Self::schedule()
+    }
+
+    fn next() -> Option<usize> {
+        let current = Current::get().with_rlock(|current| *current);
+
+        ProcessList::get().with_rlock(|processes| {
+            for i in 1..=MAX_PROCESSES {
+                let id = (current + i) % MAX_PROCESSES;
+                if id == IDLE_PROCESS {
+                    continue;
+                }
+
+                if let Some(ref process) = processes[id] {
+                    if process.with_rlock(|process| process.is_runnable()) {
+                        return Some(id);
+                    }
+                }
+            }
+
+            processes[IDLE_PROCESS].as_ref().map(|_| IDLE_PROCESS)
+        })
+    }
+}
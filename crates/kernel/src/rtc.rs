@@ -0,0 +1,91 @@
+use crate::io::{insb, outb};
+use crate::spinwhile;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0A;
+const RTC_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATING: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const HOUR_PM: u8 = 1 << 7; // Only in 12 hour mode
+
+/// A wall clock time, as the CMOS keeps it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Read the clock until it says the same twice, so an update can't tear it
+    pub fn now() -> Self {
+        let mut last = Self::read();
+        loop {
+            let now = Self::read();
+            if now == last {
+                return Self::decode(now);
+            }
+            last = now;
+        }
+    }
+
+    fn read() -> Self {
+        spinwhile!(register(RTC_STATUS_A) & STATUS_A_UPDATING != 0);
+
+        Self {
+            year: register(RTC_YEAR) as u16,
+            month: register(RTC_MONTH),
+            day: register(RTC_DAY),
+            hour: register(RTC_HOURS),
+            minute: register(RTC_MINUTES),
+            second: register(RTC_SECONDS),
+        }
+    }
+
+    /// The registers may be in BCD and the hours in 12 hour mode, the century isn't kept
+    fn decode(raw: Self) -> Self {
+        let status = register(RTC_STATUS_B);
+        let binary = |val: u8| {
+            if status & STATUS_B_BINARY != 0 {
+                val
+            } else {
+                (val >> 4) * 10 + (val & 0x0f)
+            }
+        };
+
+        let mut hour = binary(raw.hour & !HOUR_PM);
+        if status & STATUS_B_24_HOUR == 0 {
+            hour %= 12;
+            if raw.hour & HOUR_PM != 0 {
+                hour += 12;
+            }
+        }
+
+        Self {
+            year: 2000 + binary(raw.year as u8) as u16,
+            month: binary(raw.month),
+            day: binary(raw.day),
+            hour,
+            minute: binary(raw.minute),
+            second: binary(raw.second),
+        }
+    }
+}
+
+fn register(reg: u8) -> u8 {
+    outb(CMOS_ADDRESS, reg);
+    insb(CMOS_DATA)
+}
@@ -1,6 +1,12 @@
 use core::fmt::{self, Write};
 
+use interrupts::isr;
+
+use crate::cmdline::{Options, LOGLEVEL_TRACE};
+use crate::cpu::InterruptFrame;
 use crate::io::{insb, outb};
+use crate::pic::{IRQ_COM1, PIC};
+use crate::tty::Tty;
 
 const SERIAL_PORT: u16 = 0x3F8;
 
@@ -13,8 +19,9 @@ impl SerialPort {
     }
 
     fn init(&self) {
+        // Interrupt when data is available
+        outb(SERIAL_PORT + 1, 0x01);
         // Configure the baud rate
-        outb(SERIAL_PORT + 1, 0x00);
         outb(SERIAL_PORT + 3, 0x80);
         outb(SERIAL_PORT, 0x03);
         outb(SERIAL_PORT + 1, 0x00);
@@ -26,6 +33,10 @@ impl SerialPort {
     fn is_transmit_empty(&self) -> bool {
         insb(SERIAL_PORT + 5) & 0x20 != 0
     }
+
+    fn is_data_ready(&self) -> bool {
+        insb(SERIAL_PORT + 5) & 0x01 != 0
+    }
 }
 
 impl Write for SerialPort {
@@ -63,19 +74,27 @@ macro_rules! _func {
     }};
 }
 
+/// Like __trace, but quiet when the loglevel says so
+#[macro_export]
+macro_rules! __trace_log {
+    ($($arg:tt)*) => {
+        $crate::serial::_trace(format_args!($($arg)*));
+    };
+}
+
 #[macro_export]
 macro_rules! trace {
-    () => ($crate::__trace!("[{}:{}] {}", file!(), line!(), $crate::_func!()));
-    ($fmt:expr) => ($crate::__trace!(concat!("[{}:{}] ", $fmt), file!(), line!()));
-    ($fmt:expr, $($arg:tt)*) => ($crate::__trace!(
+    () => ($crate::__trace_log!("[{}:{}] {}", file!(), line!(), $crate::_func!()));
+    ($fmt:expr) => ($crate::__trace_log!(concat!("[{}:{}] ", $fmt), file!(), line!()));
+    ($fmt:expr, $($arg:tt)*) => ($crate::__trace_log!(
         concat!("[{}:{}] ", $fmt), file!(), line!(), $($arg)*));
 }
 
 #[macro_export]
 macro_rules! traceln {
-    () => ($crate::__trace!("[{}:{}] {}\n", file!(), line!(), $crate::_func!()));
-    ($fmt:expr) => ($crate::__trace!(concat!("[{}:{}] ", $fmt, "\n"), file!(), line!()));
-    ($fmt:expr, $($arg:tt)*) => ($crate::__trace!(
+    () => ($crate::__trace_log!("[{}:{}] {}\n", file!(), line!(), $crate::_func!()));
+    ($fmt:expr) => ($crate::__trace_log!(concat!("[{}:{}] ", $fmt, "\n"), file!(), line!()));
+    ($fmt:expr, $($arg:tt)*) => ($crate::__trace_log!(
         concat!("[{}:{}] ", $fmt, "\n"), file!(), line!(), $($arg)*));
 }
 
@@ -87,10 +106,48 @@ macro_rules! dbg {
         concat!("[{}:{}] ", $fmt, "\n"), file!(), line!(), $($arg)*));
 }
 
+/// Set up COM1, once before anything is written to it.
+/// Setting it up again would empty the FIFO of input not read yet.
+pub fn init() {
+    SerialPort::new().init();
+}
+
+/// Let COM1 interrupt when there is input, the PIC has to be set up for this
+pub fn enable_input() {
+    PIC::unmask(IRQ_COM1);
+}
+
+/// Write raw @bytes to COM1
+pub fn write_bytes(bytes: &[u8]) {
+    let serial_port = SerialPort::new();
+    for byte in bytes {
+        serial_port.write_byte(*byte);
+    }
+}
+
+#[isr(0x24)]
+fn serial_input(_frame: *const InterruptFrame) {
+    let serial_port = SerialPort::new();
+
+    while serial_port.is_data_ready() {
+        match insb(SERIAL_PORT) {
+            b'\r' => Tty::input(b'\n'),
+            0x7F => Tty::input(0x08), // DEL, what terminals send for backspace
+            byte => Tty::input(byte),
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn _trace(args: fmt::Arguments) {
+    if Options::loglevel() >= LOGLEVEL_TRACE {
+        _print(args);
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
     let mut serial_port = SerialPort::new();
-    serial_port.init();
     serial_port.write_fmt(args).unwrap();
 }
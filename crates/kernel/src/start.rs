@@ -1,13 +1,19 @@
-use crate::__trace;
+use crate::{__trace, heap::KernelHeap};
 use core::arch::asm;
 #[inline(never)]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     if let Some(loc) = info.location() {
-        __trace!("[{}:{}] panic - {}", loc.file(), loc.line(), info.message());
+        __trace!(
+            "[{}:{}] panic - {}\n",
+            loc.file(),
+            loc.line(),
+            info.message()
+        );
     } else {
-        __trace!("Kernel panic somwhere!");
+        __trace!("Kernel panic somwhere!\n");
     }
+    KernelHeap::trace_stats_on_panic();
 
     unsafe { asm!("hlt", options(noreturn)) }
 }
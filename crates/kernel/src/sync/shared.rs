@@ -53,14 +53,15 @@ impl<T> Shared<T> {
         unsafe { self.0.as_mut() }
     }
 
-    pub fn with_wlock<F>(&mut self, f: F)
+    pub fn with_wlock<F, U>(&mut self, f: F) -> U
     where
-        F: FnOnce(&mut T),
+        F: FnOnce(&mut T) -> U,
     {
         let inner = self.inner_mut();
         inner.rwlock.wlock();
-        f(&mut inner.data);
-        inner.rwlock.wunlock()
+        let r = f(&mut inner.data);
+        inner.rwlock.wunlock();
+        r
     }
 
     pub fn with_rlock<F, U>(&self, f: F) -> U
@@ -111,11 +112,13 @@ impl<T> Drop for Shared<T> {
 
         self.lock();
 
-        unsafe { core::ptr::drop_in_place(self.0.as_ptr()) }
-
-        drop(Weak(self.0));
+        unsafe { core::ptr::drop_in_place(&raw mut (*self.0.as_ptr()).data) }
 
         self.unlock();
+
+        // The strong references collectively hold one weak reference,
+        // this is what finally frees the allocation.
+        drop(Weak(self.0));
     }
 }
 
@@ -123,7 +126,7 @@ pub struct Weak<T>(NonNull<SharedInner<T>>);
 
 impl<T> Default for Weak<T> {
     fn default() -> Self {
-        unsafe { Weak(NonNull::new_unchecked(0x3FFFFFFF as *mut SharedInner<T>)) }
+        Weak(NonNull::dangling())
     }
 }
 
@@ -132,7 +135,38 @@ impl<T> Weak<T> {
         Self::default()
     }
 
+    // A default weak reference doesn't point to anything
+    fn is_dangling(&self) -> bool {
+        self.0 == NonNull::dangling()
+    }
+
+    /// A strong reference, if the value is still alive
+    pub fn upgrade(&self) -> Option<Shared<T>> {
+        if self.is_dangling() {
+            return None;
+        }
+
+        let inner = self.inner();
+        let mut strong = inner.strong.load(Ordering::Relaxed);
+        loop {
+            if strong == 0 {
+                return None;
+            }
+
+            match inner.strong.compare_exchange_weak(
+                strong,
+                strong + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(Shared::from_inner(self.0)),
+                Err(current) => strong = current,
+            }
+        }
+    }
+
     fn inner(&self) -> &SharedInner<T> {
+        assert!(!self.is_dangling(), "Dangling weak reference");
         unsafe { self.0.as_ref() }
     }
 
@@ -150,6 +184,10 @@ impl<T> Weak<T> {
 
 impl<T> Drop for Weak<T> {
     fn drop(&mut self) {
+        if self.is_dangling() {
+            return;
+        }
+
         let ptr = self.0.as_ptr();
         let (weak, lock) = unsafe { (&(*ptr).weak, &(*ptr).rwlock) };
 
@@ -0,0 +1,266 @@
+use crate::{
+    boxed::Array,
+    fs::{DirEntry, FileMode, IOError, SeekMode, VFS},
+    paging::{Addr, PAGE_SIZE},
+    path::Path,
+    process::{Block, CurrentProcess, Scheduler},
+    rtc::DateTime,
+    syscalls::{
+        errno::{EBADF, EINVAL, EMFILE},
+        syscall, Dirent, Stat, Time, NAME_MAX, O_APPEND, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY,
+        SEEK_CUR, SEEK_END, SEEK_SET,
+    },
+    task::CurrentTask,
+    uaccess,
+};
+
+use core::mem::size_of;
+
+use super::{error, with_user_path};
+
+// The most a single read or write moves through the kernel heap
+const MAX_TRANSFER: usize = 16 * PAGE_SIZE;
+
+/// Files are made with O_CREAT if there is none, only O_TRUNC empties them
+#[syscall(7)]
+fn open(path: *const u8, flags: u32) -> usize {
+    if flags & !(O_WRONLY | O_CREAT | O_TRUNC | O_APPEND) != 0
+        || (flags != O_RDONLY && flags & O_WRONLY == 0)
+    {
+        return error(EINVAL);
+    }
+
+    let mut mode = FileMode::READ_ONLY;
+    for (flag, bit) in [
+        (O_WRONLY, FileMode::WRITE_ONLY),
+        (O_CREAT, FileMode::CREATE),
+        (O_TRUNC, FileMode::TRUNCATE),
+        (O_APPEND, FileMode::APPEND),
+    ] {
+        if flags & flag != 0 {
+            mode = mode | bit;
+        }
+    }
+
+    with_user_path(path, |path| {
+        let file = match VFS::open(Path::new(path), mode) {
+            Ok(file) => file,
+            Err(e) => return error(e.errno()),
+        };
+
+        CurrentProcess::get()
+            .with_wlock(|process| process.add_file(file))
+            .unwrap_or_else(|| error(EMFILE))
+    })
+}
+
+/// Reads at most @count bytes, 0 means end of file.
+/// Blocks while there is nothing to read from the console.
+#[syscall(8)]
+fn read(fd: i32, buf: *mut u8, count: usize) -> usize {
+    let data = CurrentProcess::get().with_rlock(|process| {
+        process
+            .file(fd as usize)
+            .map(|file| file.read(count.min(MAX_TRANSFER)))
+    });
+
+    let mut data = match data {
+        Some(Ok(data)) => data,
+        Some(Err(IOError::WouldBlock)) => Scheduler::block(Block::Input),
+        Some(Err(e)) => return error(e.errno()),
+        None => return error(EBADF),
+    };
+
+    let ret = match uaccess::copy_to_user(&CurrentTask::get(), Addr(buf as usize), &data) {
+        Ok(()) => data.len(),
+        Err(e) => error(e.errno()),
+    };
+    data.free();
+
+    ret
+}
+
+#[syscall(9)]
+fn write(fd: i32, buf: *const u8, count: usize) -> usize {
+    let writable = CurrentProcess::get().with_rlock(|process| {
+        process
+            .file(fd as usize)
+            .map(|file| file.stat().mode.is_writable())
+    });
+
+    match writable {
+        None | Some(false) => return error(EBADF),
+        Some(true) if count == 0 => return 0,
+        Some(true) => {}
+    }
+
+    let count = count.min(MAX_TRANSFER);
+    let mut data: Array<u8> = Array::new(count);
+
+    let ret = match uaccess::copy_from_user(&CurrentTask::get(), Addr(buf as usize), &mut data) {
+        Ok(()) => CurrentProcess::get().with_wlock(|process| {
+            let Some(file) = process.file_mut(fd as usize) else {
+                return error(EBADF);
+            };
+
+            match file.write(1, count, &data) {
+                Ok(()) => count,
+                Err(e) => error(e.errno()),
+            }
+        }),
+        Err(e) => error(e.errno()),
+    };
+    data.free();
+
+    ret
+}
+
+#[syscall(10)]
+fn close(fd: i32) -> usize {
+    match CurrentProcess::get().with_wlock(|process| process.remove_file(fd as usize)) {
+        Some(_) => 0,
+        None => error(EBADF),
+    }
+}
+
+/// Returns the new position from the start of the file
+#[syscall(11)]
+fn lseek(fd: i32, offset: i32, whence: u32) -> usize {
+    let whence = match whence {
+        SEEK_SET => SeekMode::StartOfFile,
+        SEEK_CUR => SeekMode::CurrentPosition,
+        SEEK_END => SeekMode::EndOfFile,
+        _ => return error(EINVAL),
+    };
+
+    CurrentProcess::get().with_rlock(|process| {
+        let Some(file) = process.file(fd as usize) else {
+            return error(EBADF);
+        };
+
+        match file.seek(offset as isize, whence) {
+            Ok(pos) => pos,
+            Err(e) => error(e.errno()),
+        }
+    })
+}
+
+#[syscall(12)]
+fn fstat(fd: i32, stat: *mut Stat) -> usize {
+    let Some(st) = CurrentProcess::get().with_rlock(|process| {
+        process.file(fd as usize).map(|file| {
+            let st = file.stat();
+            Stat {
+                mode: if st.mode.is_writable() {
+                    O_WRONLY
+                } else {
+                    O_RDONLY
+                },
+                size: st.size as u32,
+            }
+        })
+    }) else {
+        return error(EBADF);
+    };
+
+    match uaccess::write_user(&CurrentTask::get(), Addr(stat as usize), &st) {
+        Ok(()) => 0,
+        Err(e) => error(e.errno()),
+    }
+}
+
+#[syscall(14)]
+fn unlink(path: *const u8) -> usize {
+    with_user_path(path, |path| match VFS::remove(Path::new(path)) {
+        Ok(()) => 0,
+        Err(e) => error(e.errno()),
+    })
+}
+
+#[syscall(15)]
+fn rename(from: *const u8, to: *const u8) -> usize {
+    with_user_path(from, |from| {
+        with_user_path(to, |to| match VFS::rename(Path::new(from), Path::new(to)) {
+            Ok(()) => 0,
+            Err(e) => error(e.errno()),
+        })
+    })
+}
+
+#[syscall(16)]
+fn opendir(path: *const u8) -> usize {
+    with_user_path(path, |path| {
+        let dir = match VFS::opendir(Path::new(path)) {
+            Ok(dir) => dir,
+            Err(e) => return error(e.errno()),
+        };
+
+        CurrentProcess::get()
+            .with_wlock(|process| process.add_file(dir))
+            .unwrap_or_else(|| error(EMFILE))
+    })
+}
+
+/// Fills in at most @count entries, 0 means there are no more
+#[syscall(17)]
+fn getdents(fd: i32, entries: *mut Dirent, count: usize) -> usize {
+    let task = CurrentTask::get();
+    let Some(len) = count.checked_mul(size_of::<Dirent>()) else {
+        return error(EINVAL);
+    };
+    if let Err(e) = uaccess::validate(&task, Addr(entries as usize), len, true) {
+        return error(e.errno());
+    }
+
+    CurrentProcess::get().with_rlock(|process| {
+        let Some(file) = process.file(fd as usize) else {
+            return error(EBADF);
+        };
+
+        for i in 0..count {
+            let entry = match file.readdir() {
+                Ok(Some(entry)) => entry,
+                Ok(None) => return i,
+                Err(e) => return error(e.errno()),
+            };
+
+            let vaddr = Addr(entries as usize + i * size_of::<Dirent>());
+            if let Err(e) = uaccess::write_user(&task, vaddr, &dirent(&entry)) {
+                return error(e.errno());
+            }
+        }
+
+        count
+    })
+}
+
+/// Names that don't fit in NAME_MAX are cut short
+fn dirent(entry: &DirEntry) -> Dirent {
+    let time = |t: DateTime| Time {
+        year: t.year,
+        month: t.month,
+        day: t.day,
+        hour: t.hour,
+        minute: t.minute,
+        second: t.second,
+    };
+
+    let mut dirent = Dirent {
+        name: [0; NAME_MAX + 1],
+        extension: [0; 4],
+        attributes: entry.attributes,
+        size: entry.size as u32,
+        created: time(entry.created),
+        modified: time(entry.modified),
+    };
+
+    let name = entry.name.as_bytes();
+    let len = name.len().min(NAME_MAX);
+    dirent.name[..len].copy_from_slice(&name[..len]);
+
+    let extension = entry.extension().as_bytes();
+    let len = extension.len().min(dirent.extension.len() - 1);
+    dirent.extension[..len].copy_from_slice(&extension[..len]);
+
+    dirent
+}
@@ -0,0 +1,28 @@
+use crate::{
+    heap::KernelHeap,
+    paging::{frame::Frames, Addr},
+    syscalls::{syscall, MemInfo},
+    task::CurrentTask,
+    uaccess,
+};
+
+use super::error;
+
+#[syscall(13)]
+fn meminfo(info: *mut MemInfo) -> usize {
+    let stats = KernelHeap::stats();
+    let meminfo = MemInfo {
+        heap_total: stats.total_blocks as u32,
+        heap_used: stats.used_blocks as u32,
+        heap_free: stats.free_blocks as u32,
+        heap_largest_free: stats.largest_free_run as u32,
+        heap_allocations: stats.allocations as u32,
+        heap_high_water: stats.high_water as u32,
+        free_frames: Frames::available() as u32,
+    };
+
+    match uaccess::write_user(&CurrentTask::get(), Addr(info as usize), &meminfo) {
+        Ok(()) => 0,
+        Err(e) => error(e.errno()),
+    }
+}
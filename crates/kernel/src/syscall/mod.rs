@@ -1,17 +1,28 @@
 use interrupts::interrupt_handler;
 
 use crate::{
-    cpu::{InterruptFrame, CPU},
-    io::outb,
-    paging::KernelPage,
-    process::{CurrentProcess, Process},
-    syscalls::{gen_syscalls, syscall},
+    cmdline::Options,
+    cpu::InterruptFrame,
+    paging::Addr,
+    path::MAX_PATH,
+    syscalls::{
+        errno::{EINVAL, ENAMETOOLONG, ENOSYS},
+        gen_syscalls,
+    },
     task::{CurrentTask, Task},
+    uaccess,
 };
 use core::arch::naked_asm;
 
-const NUM_SYSCALLS: usize = 1;
-gen_syscalls!(1);
+mod file;
+mod memory;
+mod process;
+use file::*;
+use memory::*;
+use process::*;
+
+const NUM_SYSCALLS: usize = 18;
+gen_syscalls!(18);
 
 #[no_mangle]
 static mut SYSCALL_RETURN: usize = 0;
@@ -49,31 +60,43 @@ pub extern "C" fn entry_syscall() {
 #[no_mangle]
 pub unsafe fn syscall_handler(command: usize, frame: *const InterruptFrame) -> usize {
     if command >= NUM_SYSCALLS {
-        outb(0x20, 0x20);
-        return 0;
+        return error(ENOSYS);
     }
 
-    KernelPage::switch();
     Task::save(CurrentTask::get(), *frame);
 
     let syscall = SYSCALLS[command];
     let frame = unsafe { &*frame };
 
-    let ret = syscall(frame);
-
-    CurrentTask::paging_switch();
-
-    ret
+    syscall(frame)
 }
 
-#[syscall(0)]
-fn exit(_: *const InterruptFrame) -> usize {
-    let task = CurrentTask::get();
-    let code = Task::copy_stack_item::<usize>(&task, 0);
-    Process::mark_dead(CurrentProcess::get(), code);
+/// Syscalls report failure as a negative errno
+pub(crate) fn error(errno: usize) -> usize {
+    (-(errno as isize)) as usize
+}
 
-    unsafe { CPU::return_to_current() };
+/// Run @f on the path the current task passed at @vaddr.
+/// Paths that don't name a disk are on the root one.
+pub(crate) fn with_user_path<F>(vaddr: *const u8, f: F) -> usize
+where
+    F: FnOnce(&str) -> usize,
+{
+    let mut buf = [0; MAX_PATH];
+    let len = match uaccess::strncpy_from_user(&CurrentTask::get(), Addr(vaddr as usize), &mut buf)
+    {
+        Ok(MAX_PATH) => return error(ENAMETOOLONG),
+        Ok(len) => len,
+        Err(e) => return error(e.errno()),
+    };
 
+    let Ok(path) = core::str::from_utf8(&buf[..len]) else {
+        return error(EINVAL);
+    };
 
-    unreachable!()
+    let mut resolved = [0; MAX_PATH];
+    match Options::resolve(path, &mut resolved) {
+        Some(path) => f(path),
+        None => error(ENAMETOOLONG),
+    }
 }
@@ -0,0 +1,109 @@
+use core::mem::size_of;
+
+use crate::{
+    cpu::CPU,
+    paging::Addr,
+    process::{Block, CurrentProcess, Process, Processes, Scheduler},
+    syscalls::{
+        errno::{ECHILD, EINVAL},
+        syscall,
+    },
+    task::CurrentTask,
+    uaccess,
+};
+
+use super::{error, with_user_path};
+
+/// Block until a child, or the child @pid, has exited and return its pid.
+/// Its exit code goes to @status, unless that is null.
+fn wait_child(pid: Option<usize>, status: *mut i32) -> usize {
+    let task = CurrentTask::get();
+    let status = Addr(status as usize);
+
+    // Checked first, a status that was collected can't be put back
+    if status.0 != 0 {
+        if let Err(e) = uaccess::validate(&task, status, size_of::<i32>(), true) {
+            return error(e.errno());
+        }
+    }
+
+    let parent = CurrentProcess::get().with_rlock(|process| process.pid());
+    if let Some(exited) = Processes::collect(parent, pid) {
+        if status.0 != 0 {
+            if let Err(e) = uaccess::write_user(&task, status, &(exited.code as i32)) {
+                return error(e.errno());
+            }
+        }
+        return exited.pid;
+    }
+
+    if !Processes::has_child(parent, pid) {
+        return error(ECHILD);
+    }
+
+    Scheduler::block(Block::Child)
+}
+
+/// Only the low 8 bits of @code are kept, so it can't be mistaken for an error
+#[syscall(0)]
+fn exit(code: i32) -> usize {
+    Process::mark_dead(CurrentProcess::get(), code as u8 as usize);
+
+    Scheduler::schedule()
+}
+
+#[syscall(1)]
+fn spawn(path: *const u8) -> usize {
+    with_user_path(path, |path| {
+        let parent = CurrentProcess::get().with_rlock(|process| process.pid());
+
+        match Process::new(path, Some(parent)) {
+            Ok(process) => process.with_rlock(|process| process.pid()),
+            Err(e) => error(e.errno()),
+        }
+    })
+}
+
+#[syscall(2)]
+fn exec(path: *const u8) -> usize {
+    let ret = with_user_path(path, |path| {
+        match Process::replace(CurrentProcess::get(), path) {
+            Ok(()) => 0,
+            Err(e) => error(e.errno()),
+        }
+    });
+
+    if ret != 0 {
+        return ret;
+    }
+
+    // There is nothing to return to, start the new image from scratch
+    unsafe { CPU::return_to_current() };
+
+    unreachable!()
+}
+
+#[syscall(3)]
+fn wait(status: *mut i32) -> usize {
+    wait_child(None, status)
+}
+
+#[syscall(4)]
+fn waitpid(pid: i32, status: *mut i32) -> usize {
+    if pid < 0 {
+        return error(EINVAL);
+    }
+
+    wait_child(Some(pid as usize), status)
+}
+
+#[syscall(5)]
+fn getpid() -> usize {
+    CurrentProcess::get().with_rlock(|process| process.pid())
+}
+
+/// Processes started by the kernel have no parent, they get 0.
+#[syscall(6)]
+fn getppid() -> usize {
+    CurrentProcess::get().with_rlock(|process| process.parent().unwrap_or(0))
+}
@@ -0,0 +1,199 @@
+use global::global;
+
+use crate::{
+    boxed::{Array, RingBuffer},
+    fs::{FileDescriptor, FileMode, FileStat, IOError, SeekMode},
+    process::{Block, Processes},
+    serial,
+};
+
+use super::Terminal;
+
+const INPUT_BUFFER_SIZE: usize = 256;
+const MAX_LINE: usize = 128;
+
+const BACKSPACE: u8 = 0x08;
+const KILL_LINE: u8 = 0x15; // Ctrl-U
+const ESCAPE: u8 = 0x1B;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    Started,  // Got ESC
+    Sequence, // Got ESC [, waiting for the final byte
+}
+
+/// The line being typed, it is only readable once enter is hit
+pub struct LineEditor {
+    line: [u8; MAX_LINE],
+    len: usize,
+    escape: Escape,
+}
+
+impl LineEditor {
+    const fn new() -> Self {
+        Self {
+            line: [0; MAX_LINE],
+            len: 0,
+            escape: Escape::None,
+        }
+    }
+
+    /// Escape sequences (arrows and friends) don't edit the line, skip them.
+    /// Returns true if @byte was part of one.
+    fn skip_escape(&mut self, byte: u8) -> bool {
+        self.escape = match (self.escape, byte) {
+            (Escape::None, ESCAPE) => Escape::Started,
+            (Escape::None, _) => return false,
+            (Escape::Started, b'[') => Escape::Sequence,
+            (Escape::Sequence, 0x20..=0x3F) => Escape::Sequence,
+            _ => Escape::None,
+        };
+
+        true
+    }
+}
+
+global!(Input, RingBuffer<u8, INPUT_BUFFER_SIZE>, RingBuffer::new(), "TTY_INPUT");
+global!(Line, LineEditor, LineEditor::new(), "TTY_LINE");
+global!(SerialMirror, bool, false, "TTY_SERIAL_MIRROR");
+
+pub struct Tty;
+impl Tty {
+    /// Feed a byte from an input device, this is called from interrupt handlers.
+    ///
+    /// Input is canonical: it is echoed and edited a line at a time, and a line
+    /// becomes readable when it is terminated by a newline.
+    pub fn input(byte: u8) {
+        Line::set(|editor| {
+            if editor.skip_escape(byte) {
+                return;
+            }
+
+            match byte {
+                BACKSPACE if editor.len > 0 => {
+                    editor.len -= 1;
+                    Self::erase();
+                }
+                KILL_LINE => {
+                    for _ in 0..editor.len {
+                        Self::erase();
+                    }
+                    editor.len = 0;
+                }
+                b'\n' => {
+                    Self::output(b"\n");
+                    Self::commit(&editor.line[..editor.len]);
+                    editor.len = 0;
+                }
+                b'\t' | 0x20..=0x7E if editor.len < MAX_LINE => {
+                    editor.line[editor.len] = byte;
+                    editor.len += 1;
+                    Self::output(&[byte]);
+                }
+                _ => {}
+            }
+        })
+    }
+
+    /// Make @line readable and wake up whoever is waiting for it
+    fn commit(line: &[u8]) {
+        Input::set(|input| {
+            for byte in line.iter().chain(b"\n") {
+                input.push(*byte);
+            }
+        });
+
+        Processes::wake_all(Block::Input);
+    }
+
+    fn erase() {
+        Terminal::write(&[BACKSPACE]);
+
+        if SerialMirror::get().with_rlock(|mirror| *mirror) {
+            serial::write_bytes(b"\x08 \x08");
+        }
+    }
+
+    /// Write @bytes to the screen, and to the serial port if mirroring is on.
+    pub fn output(bytes: &[u8]) {
+        Terminal::write(bytes);
+
+        if SerialMirror::get().with_rlock(|mirror| *mirror) {
+            serial::write_bytes(bytes);
+        }
+    }
+
+    pub fn mirror_to_serial(on: bool) {
+        SerialMirror::set(|mirror| *mirror = on);
+    }
+}
+
+/// The standard streams every process starts with
+pub enum Console {
+    Input,
+    Output,
+}
+
+impl FileDescriptor for Console {
+    /// Returns at most one line, and at most @size bytes of it
+    fn read(&self, size: usize) -> Result<Array<u8>, IOError> {
+        let Console::Input = self else {
+            return Err(IOError::InvalidArgument);
+        };
+
+        Input::get_mut().with_wlock(|input| {
+            if input.is_empty() {
+                return Err(IOError::WouldBlock);
+            }
+
+            let mut line = [0; MAX_LINE + 1];
+            let mut len = 0;
+            while len < size.min(line.len()) {
+                let Some(byte) = input.pop() else {
+                    break;
+                };
+
+                line[len] = byte;
+                len += 1;
+                if byte == b'\n' {
+                    break;
+                }
+            }
+
+            Ok(Array::from(&line[..len]))
+        })
+    }
+
+    fn read_all(&self) -> Result<Array<u8>, IOError> {
+        Err(IOError::InvalidArgument)
+    }
+
+    fn write(&mut self, size: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        let Console::Output = self else {
+            return Err(IOError::InvalidArgument);
+        };
+
+        let len = size.checked_mul(count).ok_or(IOError::InvalidArgument)?;
+        Tty::output(buf.get(..len).ok_or(IOError::InvalidArgument)?);
+
+        Ok(())
+    }
+
+    fn seek(&self, _offset: isize, _whence: SeekMode) -> Result<usize, IOError> {
+        Err(IOError::InvalidArgument)
+    }
+
+    fn stat(&self) -> FileStat {
+        let mode = match self {
+            Console::Input => FileMode::READ_ONLY,
+            Console::Output => FileMode::WRITE_ONLY,
+        };
+
+        FileStat { mode, size: 0 }
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
@@ -1,7 +1,9 @@
 use core::fmt::{self, Write};
 
+pub mod console;
 #[macro_use]
 pub mod terminal;
+pub use console::{Console, Tty};
 pub use terminal::Terminal;
 
 pub struct TypeWriter {
@@ -50,7 +52,6 @@ impl TypeWriter {
 
         self.ix -= 1;
         self.put_char(self.ix, self.iy, ' ', COLOR_WHITE);
-        self.ix -= 1;
     }
 
     fn put_char(&mut self, ix: isize, iy: isize, c: char, color: u8) {
@@ -59,6 +60,33 @@ impl TypeWriter {
         }
     }
 
+    /// Move everything up a line and clear the last one
+    fn scroll(&mut self) {
+        let width = self.width as isize;
+        let height = self.height as isize;
+        unsafe {
+            core::ptr::copy(
+                self.base.offset(width),
+                self.base,
+                self.width * (self.height - 1),
+            );
+        }
+
+        for x in 0..width {
+            self.put_char(x, height - 1, ' ', COLOR_WHITE);
+        }
+        self.iy = height - 1;
+    }
+
+    fn newline(&mut self) {
+        self.ix = 0;
+        self.iy += 1;
+
+        if self.iy >= self.height as isize {
+            self.scroll();
+        }
+    }
+
     fn write_char(&mut self, c: char, color: u8) {
         if c as u8 == 0x08 {
             self.backspace();
@@ -66,8 +94,7 @@ impl TypeWriter {
         }
 
         if c == '\n' {
-            self.ix = 0;
-            self.iy += 1;
+            self.newline();
             return;
         }
 
@@ -75,8 +102,7 @@ impl TypeWriter {
         self.ix += 1;
 
         if self.ix >= self.width as isize {
-            self.ix = 0;
-            self.iy += 1;
+            self.newline();
         }
     }
 
@@ -3,6 +3,7 @@ use global::global;
 use core::fmt::{self, Write};
 
 use super::{TypeWriter, COLOR_WHITE};
+use crate::memory::phys_to_virt;
 
 const VGA_WIDTH: usize = 80;
 const VGA_HEIGHT: usize = 25;
@@ -10,7 +11,7 @@ const VGA_HEIGHT: usize = 25;
 global! {
     Terminal,
     TypeWriter,
-    TypeWriter::new(0xB8000, VGA_WIDTH, VGA_HEIGHT),
+    TypeWriter::new(phys_to_virt(0xB8000) as u32, VGA_WIDTH, VGA_HEIGHT),
     "TERMINAL"
 }
 
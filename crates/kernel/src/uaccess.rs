@@ -0,0 +1,153 @@
+use core::mem::{size_of, MaybeUninit};
+
+use crate::memory::{phys_to_virt, KERNEL_BASE};
+use crate::paging::{Addr, Flags, PAGE_ACCESS_ALL, PAGE_IS_WRITABLE, PAGE_SIZE};
+use crate::process::Process;
+use crate::sync::Shared;
+use crate::syscalls::errno::EFAULT;
+use crate::task::Task;
+
+/// A user address that isn't mapped, or not with the access asked for
+#[derive(Debug)]
+pub struct BadAddress;
+
+impl BadAddress {
+    pub fn errno(&self) -> usize {
+        EFAULT
+    }
+}
+
+/// Make sure user land may access the @len bytes at @vaddr in @task, writing to them if @write.
+/// Pages that haven't been touched yet are faulted in.
+pub fn validate(
+    task: &Shared<Task>,
+    vaddr: Addr,
+    len: usize,
+    write: bool,
+) -> Result<(), BadAddress> {
+    let flags = if write { PAGE_IS_WRITABLE } else { 0 };
+    for_each_page(vaddr, len, |vaddr, _| {
+        kernel_addr(task, vaddr, flags).map(|_| ())
+    })
+}
+
+/// Copy @buf.len() bytes at @vaddr in @task into @buf
+pub fn copy_from_user(task: &Shared<Task>, vaddr: Addr, buf: &mut [u8]) -> Result<(), BadAddress> {
+    let mut done = 0;
+    for_each_page(vaddr, buf.len(), |vaddr, n| {
+        let addr = kernel_addr(task, vaddr, 0)?;
+        unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, buf[done..].as_mut_ptr(), n) };
+        done += n;
+        Ok(())
+    })
+}
+
+/// Copy @buf to @vaddr in @task
+pub fn copy_to_user(task: &Shared<Task>, vaddr: Addr, buf: &[u8]) -> Result<(), BadAddress> {
+    let mut done = 0;
+    for_each_page(vaddr, buf.len(), |vaddr, n| {
+        let addr = kernel_addr(task, vaddr, PAGE_IS_WRITABLE)?;
+        unsafe { core::ptr::copy_nonoverlapping(buf[done..].as_ptr(), addr as *mut u8, n) };
+        done += n;
+        Ok(())
+    })
+}
+
+/// The @T at @vaddr in @task
+pub fn read_user<T: Copy>(task: &Shared<Task>, vaddr: Addr) -> Result<T, BadAddress> {
+    let mut t = MaybeUninit::<T>::uninit();
+    let bytes = unsafe { core::slice::from_raw_parts_mut(t.as_mut_ptr().cast(), size_of::<T>()) };
+
+    copy_from_user(task, vaddr, bytes)?;
+    Ok(unsafe { t.assume_init() })
+}
+
+/// Copy @t to @vaddr in @task
+pub fn write_user<T: Copy>(task: &Shared<Task>, vaddr: Addr, t: &T) -> Result<(), BadAddress> {
+    let bytes = unsafe { core::slice::from_raw_parts((t as *const T).cast(), size_of::<T>()) };
+    copy_to_user(task, vaddr, bytes)
+}
+
+/// The word at @idx on the stack of @task, where syscalls find their arguments
+pub fn stack_item<T: Copy>(task: &Shared<Task>, idx: usize) -> Result<T, BadAddress> {
+    let sp = task.with_rlock(|task| task.registers.sp);
+    let vaddr = idx
+        .checked_mul(size_of::<usize>())
+        .and_then(|offset| sp.checked_add(offset))
+        .ok_or(BadAddress)?;
+
+    read_user(task, Addr(vaddr))
+}
+
+/// Copy the NUL-terminated string at @vaddr in @task into @buf, at most @buf.len() bytes.
+/// Returns its length, which is @buf.len() if there was no NUL in that many.
+pub fn strncpy_from_user(
+    task: &Shared<Task>,
+    vaddr: Addr,
+    buf: &mut [u8],
+) -> Result<usize, BadAddress> {
+    let mut done = 0;
+    while done < buf.len() {
+        // The string may end before a page that isn't mapped, so they are checked as we go
+        let vaddr = vaddr
+            .0
+            .checked_add(done)
+            .filter(|&vaddr| vaddr < KERNEL_BASE)
+            .ok_or(BadAddress)?;
+        let n = (PAGE_SIZE - vaddr % PAGE_SIZE).min(buf.len() - done);
+        let addr = kernel_addr(task, Addr(vaddr), 0)?;
+        let src = unsafe { core::slice::from_raw_parts(addr as *const u8, n) };
+
+        buf[done..done + n].copy_from_slice(src);
+        if let Some(nul) = src.iter().position(|&byte| byte == 0) {
+            return Ok(done + nul);
+        }
+        done += n;
+    }
+
+    Ok(buf.len())
+}
+
+/// Call @f with the start and length of each piece of @vaddr..@vaddr+@len within a page
+fn for_each_page<F>(vaddr: Addr, len: usize, mut f: F) -> Result<(), BadAddress>
+where
+    F: FnMut(Addr, usize) -> Result<(), BadAddress>,
+{
+    match vaddr.0.checked_add(len) {
+        Some(end) if end <= KERNEL_BASE => {}
+        _ => return Err(BadAddress),
+    }
+
+    let mut done = 0;
+    while done < len {
+        let vaddr = vaddr.0 + done;
+        let n = (PAGE_SIZE - vaddr % PAGE_SIZE).min(len - done);
+        f(Addr(vaddr), n)?;
+        done += n;
+    }
+
+    Ok(())
+}
+
+/// Where the kernel sees @vaddr, if user land may access it with @flags.
+/// Pages that haven't been touched yet are faulted in.
+fn kernel_addr(task: &Shared<Task>, vaddr: Addr, flags: Flags) -> Result<usize, BadAddress> {
+    let flags = flags | PAGE_ACCESS_ALL;
+    let lookup = |task: &Task| {
+        let (paddr, have) = task.page_directory.translate(vaddr)?;
+        (have & flags == flags).then(|| phys_to_virt(paddr.0))
+    };
+
+    if let Some(addr) = task.with_rlock(lookup) {
+        return Ok(addr);
+    }
+
+    let process = task
+        .with_rlock(|task| task.process.upgrade())
+        .ok_or(BadAddress)?;
+    if !Process::fault_in(process, vaddr) {
+        return Err(BadAddress);
+    }
+
+    task.with_rlock(lookup).ok_or(BadAddress)
+}
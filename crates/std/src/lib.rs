@@ -1,9 +1,12 @@
 #![no_std]
 #![no_main]
 #![feature(naked_functions)]
+#![feature(c_variadic)]
 
 extern crate syscalls;
 
+mod stdio;
+
 extern "C" {
     pub fn main() -> usize;
 }
@@ -0,0 +1,141 @@
+use core::ffi::{c_char, c_int, c_uint, CStr, VaList};
+use core::fmt::{self, Write};
+
+use syscalls::write;
+
+const STDOUT: i32 = 1;
+
+// printf goes through this so that it doesn't do a syscall per character
+const BUFFER_SIZE: usize = 128;
+
+struct Stdout {
+    buf: [u8; BUFFER_SIZE],
+    len: usize,
+    written: usize,
+}
+
+impl Stdout {
+    fn new() -> Self {
+        Self {
+            buf: [0; BUFFER_SIZE],
+            len: 0,
+            written: 0,
+        }
+    }
+
+    fn put(&mut self, byte: u8) {
+        if self.len == BUFFER_SIZE {
+            self.flush();
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+        self.written += 1;
+    }
+
+    fn put_all(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.put(*byte);
+        }
+    }
+
+    fn flush(&mut self) {
+        let mut done = 0;
+        while done < self.len {
+            let ret = unsafe { write(STDOUT, self.buf[done..].as_ptr(), self.len - done) };
+            if ret <= 0 {
+                break;
+            }
+            done += ret as usize;
+        }
+
+        self.len = 0;
+    }
+}
+
+impl Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.put_all(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Writes @s followed by a newline
+///
+/// # Safety
+///  @s must be a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn puts(s: *const c_char) -> c_int {
+    let mut out = Stdout::new();
+    out.put_all(unsafe { CStr::from_ptr(s) }.to_bytes());
+    out.put(b'\n');
+    out.flush();
+
+    out.written as c_int
+}
+
+#[no_mangle]
+pub extern "C" fn putchar(c: c_int) -> c_int {
+    let mut out = Stdout::new();
+    out.put(c as u8);
+    out.flush();
+
+    c
+}
+
+/// Supports %d %i %u %x %p %c %s and %%, without flags or widths.
+///
+/// # Safety
+///  @fmt must be a NUL-terminated string and the arguments must match it
+#[no_mangle]
+pub unsafe extern "C" fn printf(fmt: *const c_char, mut args: ...) -> c_int {
+    let mut out = Stdout::new();
+    unsafe { format(&mut out, CStr::from_ptr(fmt).to_bytes(), args.as_va_list()) };
+    out.flush();
+
+    out.written as c_int
+}
+
+unsafe fn format(out: &mut Stdout, fmt: &[u8], mut args: VaList) {
+    let mut bytes = fmt.iter();
+
+    while let Some(&byte) = bytes.next() {
+        if byte != b'%' {
+            out.put(byte);
+            continue;
+        }
+
+        // Writing to Stdout never fails
+        let _ = match bytes.next() {
+            Some(b'd' | b'i') => write!(out, "{}", unsafe { args.arg::<c_int>() }),
+            Some(b'u') => write!(out, "{}", unsafe { args.arg::<c_uint>() }),
+            Some(b'x') => write!(out, "{:x}", unsafe { args.arg::<c_uint>() }),
+            Some(b'p') => write!(out, "{:#x}", unsafe { args.arg::<usize>() }),
+            Some(b'c') => {
+                out.put(unsafe { args.arg::<c_int>() } as u8);
+                Ok(())
+            }
+            Some(b's') => {
+                let s = unsafe { args.arg::<*const c_char>() };
+                if s.is_null() {
+                    out.put_all(b"(null)");
+                } else {
+                    out.put_all(unsafe { CStr::from_ptr(s) }.to_bytes());
+                }
+                Ok(())
+            }
+            Some(b'%') => {
+                out.put(b'%');
+                Ok(())
+            }
+            Some(&other) => {
+                out.put_all(&[b'%', other]);
+                Ok(())
+            }
+            None => {
+                out.put(b'%');
+                Ok(())
+            }
+        };
+    }
+}
@@ -1 +1,125 @@
+/* Syscalls return a negative errno on failure */
+#define ENOENT 2
+#define ESRCH 3
+#define EIO 5
+#define ENOEXEC 8
+#define EBADF 9
+#define ECHILD 10
+#define EAGAIN 11
+#define ENOMEM 12
+#define EACCES 13
+#define EFAULT 14
+#define EBUSY 16
+#define EEXIST 17
+#define ENODEV 19
+#define ENOTDIR 20
+#define EISDIR 21
+#define EINVAL 22
+#define EMFILE 24
+#define ENOSPC 28
+#define ENAMETOOLONG 36
+#define ENOSYS 38
+
+/* Flags for open, O_CREAT, O_TRUNC and O_APPEND only go with O_WRONLY */
+#define O_RDONLY 0
+#define O_WRONLY 1
+#define O_CREAT 0100 /* Make the file if there is none */
+#define O_TRUNC 01000 /* Empty the file if there is one */
+#define O_APPEND 02000 /* Every write goes at the end */
+
+#define STDIN_FILENO 0
+#define STDOUT_FILENO 1
+#define STDERR_FILENO 2
+
+/* Whence for lseek */
+#define SEEK_SET 0
+#define SEEK_CUR 1
+#define SEEK_END 2
+
+struct stat {
+    unsigned int mode;
+    unsigned int size;
+};
+
+/* The heap is counted in blocks */
+struct meminfo {
+    unsigned int heap_total;
+    unsigned int heap_used;
+    unsigned int heap_free;
+    unsigned int heap_largest_free; /* The most free blocks in a row */
+    unsigned int heap_allocations;
+    unsigned int heap_high_water;   /* The most blocks ever used at once */
+    unsigned int free_frames;
+};
+
+/* Attributes of a directory entry */
+#define DIRENT_READ_ONLY (1 << 0)
+#define DIRENT_HIDDEN (1 << 1)
+#define DIRENT_SYSTEM (1 << 2)
+#define DIRENT_DIRECTORY (1 << 4)
+#define DIRENT_ARCHIVED (1 << 5)
+
+#define NAME_MAX 255
+
+struct time {
+    unsigned short year;
+    unsigned char month;
+    unsigned char day;
+    unsigned char hour;
+    unsigned char minute;
+    unsigned char second;
+};
+
+struct dirent {
+    char name[NAME_MAX + 1]; /* With the extension */
+    char extension[4];       /* Empty if there is none */
+    unsigned int attributes;
+    unsigned int size;
+    struct time created;
+    struct time modified;
+};
+
+/* Paths that don't start with a disk, like "/HELLO", are on the root disk (root= at boot) */
+
+/* Only the low 8 bits of @code reach the parent */
 void exit(int code);
+
+/* Start the program at @path (e.g. "0:/HELLO") as a child, returns its pid */
+int spawn(const char *path);
+/* Replace the running program with @path, only returns on failure */
+int exec(const char *path);
+/* Block until a child has exited, returns its pid and puts its exit code in @status */
+int wait(int *status);
+/* Block until the child @pid has exited, returns @pid and puts its exit code in @status.
+ * @status may be 0 for both. */
+int waitpid(int pid, int *status);
+int getpid(void);
+int getppid(void);
+
+/* Open @path (e.g. "0:/HELLO.TXT"), returns the file descriptor */
+int open(const char *path, unsigned int flags);
+/* Returns the number of bytes read, 0 at the end of the file */
+int read(int fd, void *buf, unsigned int count);
+/* Returns the number of bytes written */
+int write(int fd, const void *buf, unsigned int count);
+int close(int fd);
+/* Returns the new position from the start of the file */
+int lseek(int fd, int offset, unsigned int whence);
+int fstat(int fd, struct stat *st);
+/* Fill in @info with how much kernel memory is used and free */
+int meminfo(struct meminfo *info);
+/* Fails with EBUSY while @path is open */
+int unlink(const char *path);
+/* Move @from to @to, which must not exist yet, fails with EBUSY while @from is open */
+int rename(const char *from, const char *to);
+/* Open the directory @path (e.g. "0:/BIN") for getdents, close it with close.
+ * Fails with ENOTDIR if @path is a file. */
+int opendir(const char *path);
+/* Fill in at most @count entries of the directory @fd, returns how many, 0 after the last */
+int getdents(int fd, struct dirent *entries, unsigned int count);
+
+/* Write @s and a newline to stdout */
+int puts(const char *s);
+int putchar(int c);
+/* Supports %d %i %u %x %p %c %s and %%, without flags or widths */
+int printf(const char *fmt, ...);
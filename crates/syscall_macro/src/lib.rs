@@ -88,38 +88,23 @@ fn __syscalls_user__(_: TokenStream, item: TokenStream) -> TokenStream {
     let mut items = parse_macro_input!(item as ItemForeignMod);
     for (i, item) in items.items.iter_mut().enumerate() {
         if let ForeignItem::Fn(ForeignItemFn { ref mut sig, .. }) = item {
-            let arg_count = sig.inputs.len();
             let args = &sig.inputs;
+            let output = &sig.output;
             let ident = &sig.ident;
-            let body = if arg_count == 0 {
-                format!(
-                    r#"
-                        push ebp;
-                        mov eax, {i};
-                        int 0x80;
-                        pop ebp;
-                        ret;
+            // The arguments are left where the C calling convention put them,
+            // right above the return address, for the kernel to pick up.
+            let body = format!(
+                r#"
+                    mov eax, {i};
+                    int 0x80;
+                    ret;
                 "#
-                )
-            } else {
-                format!(
-                    r#"
-                        push ebp;
-                        mov eax, {i};
-                        add esp, {0};
-                        int 0x80;
-                        sub esp, {0};
-                        pop ebp;
-                        ret;
-                            "#,
-                    4 * arg_count
-                )
-            };
+            );
 
             expanded.extend(quote! {
                 #[naked]
                 #[no_mangle]
-                pub unsafe extern "C" fn #ident(#args) {
+                pub unsafe extern "C" fn #ident(#args) #output {
                     unsafe {core::arch::naked_asm!( #body)}
                 }
             });
@@ -138,13 +123,15 @@ pub fn syscall(attr: TokenStream, item: TokenStream) -> TokenStream {
     let uniform_name = uniform_syscall_name(number);
     let mut decl = Vec::new();
 
-    let args_count = input_fn.sig.inputs.len();
     for (i, arg) in input_fn.sig.inputs.into_iter().enumerate() {
         if let syn::FnArg::Typed(PatType { pat, ty, .. }) = arg {
             if let syn::Pat::Ident(ref ident) = *pat {
-                let idx = args_count - i - 1;
+                // The user stack holds the return address of the syscall wrapper, then the arguments
+                let idx = i + 1;
                 let exp = quote! {
-                    let #ident: #ty = crate::task::Task::copy_stack_item(crate::task::CurrentTask::get(), #idx);
+                    let Ok(#ident) = crate::uaccess::stack_item::<#ty>(&crate::task::CurrentTask::get(), #idx) else {
+                        return crate::syscall::error(crate::syscalls::errno::EFAULT);
+                    };
                 };
                 decl.push(exp);
             }
@@ -156,7 +143,7 @@ pub fn syscall(attr: TokenStream, item: TokenStream) -> TokenStream {
     let body = input_fn.block;
 
     let expanded = quote! {
-        fn #uniform_name(frame: &crate::cpu::InterruptFrame) -> usize {
+        pub(crate) fn #uniform_name(frame: &crate::cpu::InterruptFrame) -> usize {
             #(#decl)*
 
             #body
@@ -7,7 +7,114 @@ pub use syscall_macro::{gen_syscalls, syscall};
 
 pub use syscall_macro::syscalls;
 
+// Syscalls return a negative errno on failure
+pub mod errno {
+    pub const ENOENT: usize = 2;
+    pub const ESRCH: usize = 3;
+    pub const EIO: usize = 5;
+    pub const ENOEXEC: usize = 8;
+    pub const EBADF: usize = 9;
+    pub const ECHILD: usize = 10;
+    pub const EAGAIN: usize = 11;
+    pub const ENOMEM: usize = 12;
+    pub const EACCES: usize = 13;
+    pub const EFAULT: usize = 14;
+    pub const EBUSY: usize = 16;
+    pub const EEXIST: usize = 17;
+    pub const ENODEV: usize = 19;
+    pub const ENOTDIR: usize = 20;
+    pub const EISDIR: usize = 21;
+    pub const EINVAL: usize = 22;
+    pub const EMFILE: usize = 24;
+    pub const ENOSPC: usize = 28;
+    pub const ENAMETOOLONG: usize = 36;
+    pub const ENOSYS: usize = 38;
+}
+
+// Flags for open, O_CREAT, O_TRUNC and O_APPEND only go with O_WRONLY
+pub const O_RDONLY: u32 = 0;
+pub const O_WRONLY: u32 = 1;
+pub const O_CREAT: u32 = 0o100; // Make the file if there is none
+pub const O_TRUNC: u32 = 0o1000; // Empty the file if there is one
+pub const O_APPEND: u32 = 0o2000; // Every write goes at the end
+
+// Whence for lseek
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+/// What fstat fills in
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Stat {
+    pub mode: u32,
+    pub size: u32,
+}
+
+/// What meminfo fills in, the heap is counted in blocks
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct MemInfo {
+    pub heap_total: u32,
+    pub heap_used: u32,
+    pub heap_free: u32,
+    pub heap_largest_free: u32,
+    pub heap_allocations: u32,
+    pub heap_high_water: u32,
+    pub free_frames: u32,
+}
+
+// Attributes of a directory entry
+pub const DIRENT_READ_ONLY: u32 = 1 << 0;
+pub const DIRENT_HIDDEN: u32 = 1 << 1;
+pub const DIRENT_SYSTEM: u32 = 1 << 2;
+pub const DIRENT_DIRECTORY: u32 = 1 << 4;
+pub const DIRENT_ARCHIVED: u32 = 1 << 5;
+
+pub const NAME_MAX: usize = 255;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Time {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// What getdents fills in for each entry
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Dirent {
+    pub name: [u8; NAME_MAX + 1], // NUL-terminated, with the extension
+    pub extension: [u8; 4],       // NUL-terminated, empty if there is none
+    pub attributes: u32,
+    pub size: u32,
+    pub created: Time,
+    pub modified: Time,
+}
+
+// The position of a syscall in this block is its number
 #[syscalls]
 extern "C" {
     pub fn exit(code: i32) -> usize;
+    pub fn spawn(path: *const u8) -> i32;
+    pub fn exec(path: *const u8) -> i32;
+    pub fn wait(status: *mut i32) -> i32;
+    pub fn waitpid(pid: i32, status: *mut i32) -> i32;
+    pub fn getpid() -> i32;
+    pub fn getppid() -> i32;
+    pub fn open(path: *const u8, flags: u32) -> i32;
+    pub fn read(fd: i32, buf: *mut u8, count: usize) -> i32;
+    pub fn write(fd: i32, buf: *const u8, count: usize) -> i32;
+    pub fn close(fd: i32) -> i32;
+    pub fn lseek(fd: i32, offset: i32, whence: u32) -> i32;
+    pub fn fstat(fd: i32, stat: *mut Stat) -> i32;
+    pub fn meminfo(info: *mut MemInfo) -> i32;
+    pub fn unlink(path: *const u8) -> i32;
+    pub fn rename(from: *const u8, to: *const u8) -> i32;
+    pub fn opendir(path: *const u8) -> i32;
+    pub fn getdents(fd: i32, entries: *mut Dirent, count: usize) -> i32;
 }
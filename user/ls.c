@@ -0,0 +1,63 @@
+#include "stdlib.h"
+
+/* Programs get no arguments, so this lists the top of the root disk */
+#define DIRECTORY "/"
+#define BATCH 8
+
+/* @n with a leading zero if it has a single digit */
+static void print_two(unsigned int n)
+{
+    if (n < 10)
+        putchar('0');
+    printf("%u", n);
+}
+
+static void print_attributes(unsigned int attributes)
+{
+    putchar(attributes & DIRENT_DIRECTORY ? 'd' : '-');
+    putchar(attributes & DIRENT_READ_ONLY ? 'r' : '-');
+    putchar(attributes & DIRENT_HIDDEN ? 'h' : '-');
+    putchar(attributes & DIRENT_SYSTEM ? 's' : '-');
+    putchar(attributes & DIRENT_ARCHIVED ? 'a' : '-');
+}
+
+/* Like "d---a 2024-05-01 12:30 0 NAME" */
+static void print_entry(const struct dirent *entry)
+{
+    const struct time *modified = &entry->modified;
+
+    print_attributes(entry->attributes);
+    printf(" %u-", modified->year);
+    print_two(modified->month);
+    putchar('-');
+    print_two(modified->day);
+    putchar(' ');
+    print_two(modified->hour);
+    putchar(':');
+    print_two(modified->minute);
+    printf(" %u %s\n", entry->size, entry->name);
+}
+
+int main(void)
+{
+    struct dirent entries[BATCH];
+    int fd = opendir(DIRECTORY);
+    if (fd < 0) {
+        printf("ls: cannot open %s: %d\n", DIRECTORY, -fd);
+        return 1;
+    }
+
+    int count;
+    while ((count = getdents(fd, entries, BATCH)) > 0) {
+        for (int i = 0; i < count; i++)
+            print_entry(&entries[i]);
+    }
+    close(fd);
+
+    if (count < 0) {
+        printf("ls: cannot read %s: %d\n", DIRECTORY, -count);
+        return 1;
+    }
+
+    return 0;
+}